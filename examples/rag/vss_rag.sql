@@ -8,21 +8,27 @@ LOAD vss;
 -- at this moment, only embed function is available in community. To use embed_jina, 
 -- you'll need to build the extension locally (see README.md)
 CREATE TABLE vector_table AS
-SELECT *, embed(text)::FLOAT[384] as embedded_text FROM read_csv_auto('chunks.csv');
+SELECT *, embed(text) as embedded_text FROM read_csv_auto('chunks.csv');
 CREATE INDEX hnsw_index on vector_table USING HNSW (embedded_text);
 
+-- chunks.csv was chunked beforehand; chunk_text does the same from a raw document,
+-- with chunks sized for the embedding model:
+-- SET VARIABLE book = (SELECT content FROM read_text('book.txt'));
+-- CREATE TABLE vector_table AS
+-- SELECT chunk_text AS text, embed(chunk_text) AS embedded_text FROM chunk_text(getvariable('book'));
+
 
 -- GETTING MOST IMPORTANT CHUNKS BASED ON QUESTION
 SELECT text FROM vector_table
-ORDER BY array_distance(embedded_text, embed('In which year was the first book published?')::FLOAT[384])
+ORDER BY array_distance(embedded_text, embed('In which year was the first book published?'))
 LIMIT 5;
 
 
 SELECT text FROM vector_table
-ORDER BY array_distance(embedded_text, embed('What is the plot of the Lord of the Rings book?')::FLOAT[384])
+ORDER BY array_distance(embedded_text, embed('What is the plot of the Lord of the Rings book?'))
 LIMIT 5;
 
 
 SELECT text FROM vector_table
-ORDER BY array_distance(embedded_text, embed('What are the critics of the book?')::FLOAT[384])
+ORDER BY array_distance(embedded_text, embed('What are the critics of the book?'))
 LIMIT 5;
\ No newline at end of file
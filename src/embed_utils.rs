@@ -1,14 +1,29 @@
+use crate::hash::fnv1a;
 use candle_core::{Device, Tensor};
 use candle_nn::VarBuilder;
-use candle_transformers::models::bert::{
-    BertModel, Config, HiddenAct, PositionEmbeddingType, DTYPE,
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use hf_hub::{
+    api::sync::{Api, ApiError},
+    Cache, Repo, RepoType,
 };
-use hf_hub::{api::sync::Api, Repo, RepoType};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
-use tokenizers::{PaddingParams, Tokenizer};
+use tokenizers::Tokenizer;
+mod cross_encoder;
 mod jina_implementation;
+mod long_text;
+mod pooling;
+mod sentence_transformers;
+mod tokenization;
+use cross_encoder::ClassificationHead;
 use jina_implementation::{Config as JinaConfig, JinaModel};
+pub use long_text::WindowCombine;
+pub use pooling::Pooling;
+use sentence_transformers::SentencePipeline;
+use tokenization::{length_batches, TextTokenizer};
+pub use tokenization::{TokenPieces, TokenizedText, Truncation};
 
 #[derive(Error, Debug)]
 pub enum EmbeddingError {
@@ -29,79 +44,80 @@ pub enum EmbeddingError {
 
     #[error("Model type error: {0}")]
     ModelTypeError(String),
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error("Panicked while {0}")]
+    Panic(String),
+
+    #[error("Lock poisoned: {0}")]
+    Poisoned(String),
 }
 
 pub struct TextEmbedder {
     model: Box<dyn EmbedModel>,
-    tokenizer: Tokenizer,
+    tokenizer: TextTokenizer,
+    pipeline: SentencePipeline,
+    /// Present for cross-encoders (`BertForSequenceClassification` checkpoints).
+    classification_head: Option<ClassificationHead>,
+    /// Identifies the checkpoint and its revision in embedding cache keys.
+    fingerprint: String,
 }
 
 #[derive(Clone, Debug)]
 pub enum ModelType {
     Bert(Device),
     Jina(Device),
+    /// Cross-encoder scoring (query, document) pairs.
+    MsMarco(Device),
+    /// Any compatible checkpoint registered at run time.
+    Custom(ModelSource, Device),
 }
 
-impl ModelType {
-    fn get_model_id(&self) -> String {
-        match &self {
-            Self::Bert(_) => "sentence-transformers/all-MiniLM-L6-v2".to_string(),
-            Self::Jina(_) => "jinaai/jina-embeddings-v2-base-en".to_string(),
-        }
-    }
-
-    fn get_jina_model(&self, vb: VarBuilder) -> Result<JinaModel, EmbeddingError> {
-        match &self {
-            Self::Jina(_) => {
-                let config = JinaConfig::v2_base();
-                Ok(JinaModel::load(vb, &config)?)
-            }
-            _ => Err(EmbeddingError::ModelTypeError(
-                "Incorrect Model Type".into(),
-            )),
-        }
-    }
+/// Where a model's files come from.
+#[derive(Clone, Debug)]
+pub enum ModelSource {
+    /// Hugging Face Hub repo id, optionally pinned to a branch, tag or commit.
+    Hub {
+        repo_id: String,
+        revision: Option<String>,
+    },
+    /// Folder holding `config.json`, `tokenizer.json` and `model.safetensors`.
+    Local(PathBuf),
+}
 
-    fn get_bert_model(&self, vb: VarBuilder) -> Result<BertModel, EmbeddingError> {
-        match &self {
-            Self::Bert(_) => {
-                let config = Config {
-                    vocab_size: 30522,
-                    hidden_size: 384,
-                    num_hidden_layers: 6,
-                    num_attention_heads: 12,
-                    intermediate_size: 1536,
-                    hidden_act: HiddenAct::Gelu,
-                    hidden_dropout_prob: 0.1,
-                    max_position_embeddings: 512,
-                    type_vocab_size: 2,
-                    initializer_range: 0.02,
-                    layer_norm_eps: 1e-12,
-                    pad_token_id: 0,
-                    position_embedding_type: PositionEmbeddingType::Absolute,
-                    use_cache: true,
-                    classifier_dropout: None,
-                    model_type: Some("bert".to_string()),
-                };
-                Ok(BertModel::load(vb, &config)?)
-            }
-            _ => Err(EmbeddingError::ModelTypeError(
-                "Incorrect Model Type".into(),
-            )),
+impl ModelSource {
+    /// An existing directory is a local model, anything else a hub repo id.
+    pub fn parse(source: &str, revision: Option<String>) -> Result<Self, EmbeddingError> {
+        let path = Path::new(source);
+        if !path.is_dir() {
+            return Ok(Self::Hub {
+                repo_id: source.to_string(),
+                revision,
+            });
         }
-    }
-
-    fn get_local_model_path(&self) -> Option<PathBuf> {
-        match &self {
-            Self::Bert(_) => std::env::var("BERT_MODEL_FOLDER").ok().map(PathBuf::from),
-            Self::Jina(_) => std::env::var("JINA_MODEL_FOLDER").ok().map(PathBuf::from),
+        if revision.is_some() {
+            return Err(EmbeddingError::ModelTypeError(format!(
+                "'{}' is a local folder, a revision only applies to Hugging Face Hub models",
+                source
+            )));
         }
+        Ok(Self::Local(path.to_path_buf()))
     }
 
-    fn load_from_local(&self, local_path: &Path) -> Result<(PathBuf, PathBuf), EmbeddingError> {
+    fn load_from_local(local_path: &Path) -> Result<ModelFiles, EmbeddingError> {
+        let config_path = local_path.join("config.json");
         let tokenizer_path = local_path.join("tokenizer.json");
         let weights_path = local_path.join("model.safetensors");
 
+        if !config_path.exists() {
+            return Err(EmbeddingError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Model config file not found at {:?}", config_path),
+            )));
+        }
+
         if !tokenizer_path.exists() {
             return Err(EmbeddingError::Io(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
@@ -116,51 +132,395 @@ impl ModelType {
             )));
         }
 
-        Ok((tokenizer_path, weights_path))
+        Ok(ModelFiles {
+            config: config_path,
+            tokenizer: tokenizer_path,
+            weights: weights_path,
+            cached: true,
+        })
+    }
+
+    fn hub_repo(repo_id: &str, revision: Option<&str>) -> Repo {
+        match revision {
+            Some(revision) => {
+                Repo::with_revision(repo_id.to_string(), RepoType::Model, revision.to_string())
+            }
+            None => Repo::new(repo_id.to_string(), RepoType::Model),
+        }
     }
 
-    fn load_from_hub(&self) -> Result<(PathBuf, PathBuf), EmbeddingError> {
-        let model_id = self.get_model_id();
-        let repo = Repo::new(model_id, RepoType::Model);
-        let api = Api::new()?;
-        let api = api.repo(repo);
+    fn load_from_hub(repo_id: &str, revision: Option<&str>) -> Result<ModelFiles, EmbeddingError> {
+        let repo = Self::hub_repo(repo_id, revision);
+        let cached = ["config.json", "tokenizer.json", "model.safetensors"]
+            .iter()
+            .all(|name| Cache::default().repo(repo.clone()).get(name).is_some());
+        let api = Api::new()?.repo(repo);
+        let config = api.get("config.json")?;
         let tokenizer = api.get("tokenizer.json")?;
         let weights = api.get("model.safetensors")?;
-        Ok((tokenizer, weights))
+        Ok(ModelFiles {
+            config,
+            tokenizer,
+            weights,
+            cached,
+        })
     }
 
-    pub fn build_text_embedder(&self) -> Result<TextEmbedder, EmbeddingError> {
-        let device = match &self {
+    fn files(&self) -> Result<ModelFiles, EmbeddingError> {
+        match &self {
+            Self::Hub { repo_id, revision } => Self::load_from_hub(repo_id, revision.as_deref()),
+            Self::Local(local_path) => Self::load_from_local(local_path),
+        }
+    }
+
+    /// Names the exact model and extension version embeddings come from, so
+    /// that swapping either changes it: a Hub repo with the commit its files
+    /// were resolved to (the snapshot folder of the download), a local folder
+    /// with the size and modification time of each `.safetensors` file and a
+    /// hash of every other file it was built from (configs, tokenizer and
+    /// sentence-transformers modules). `files` starts with the weights.
+    fn fingerprint(&self, files: &[PathBuf]) -> String {
+        let model = match &self {
+            Self::Hub { repo_id, .. } => {
+                let commit = files
+                    .first()
+                    .and_then(|file| file.parent())
+                    .and_then(Path::file_name)
+                    .map_or_else(String::new, |commit| commit.to_string_lossy().into_owned());
+                format!("hub:{}@{}", repo_id, commit)
+            }
+            Self::Local(local_path) => {
+                let mut stamps = Vec::new();
+                for file in files {
+                    let relative = file.strip_prefix(local_path).unwrap_or(file);
+                    stamps.extend_from_slice(relative.to_string_lossy().as_bytes());
+                    stamps.push(0xff);
+                    if file
+                        .extension()
+                        .is_some_and(|extension| extension == "safetensors")
+                    {
+                        stamps.extend_from_slice(file_stamp(file).as_bytes());
+                    } else {
+                        stamps.extend(std::fs::read(file).unwrap_or_default());
+                    }
+                    stamps.push(0xff);
+                }
+                format!("local:{}@{:032x}", local_path.display(), fnv1a(&stamps))
+            }
+        };
+        format!("{}|quackformers {}", model, env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Fetches a file relative to the model root, `None` when the model doesn't have it.
+    /// `model_cached` tells whether the model's core files all came from the
+    /// local Hub cache.
+    fn optional_file(
+        &self,
+        name: &str,
+        model_cached: bool,
+    ) -> Result<Option<PathBuf>, EmbeddingError> {
+        match &self {
+            Self::Hub { repo_id, revision } => {
+                let repo = Self::hub_repo(repo_id, revision.as_deref());
+                if let Some(path) = Cache::default().repo(repo.clone()).get(name) {
+                    return Ok(Some(path));
+                }
+                match Api::new()?.repo(repo).get(name) {
+                    Ok(path) => Ok(Some(path)),
+                    Err(error) if is_not_found(&error) => Ok(None),
+                    // hf-hub doesn't remember which files a repo lacks, so a
+                    // model loaded from the cache, maybe offline, takes an
+                    // uncached file as absent
+                    Err(_) if model_cached => Ok(None),
+                    // a timeout must not pass for "no such file" and silently
+                    // change the pipeline
+                    Err(error) => Err(error.into()),
+                }
+            }
+            Self::Local(local_path) => {
+                let path = local_path.join(name);
+                Ok(path.exists().then_some(path))
+            }
+        }
+    }
+}
+
+/// Size and modification time of a file, which change whenever it is rewritten.
+fn file_stamp(path: &Path) -> String {
+    let (len, modified) = std::fs::metadata(path)
+        .map(|metadata| {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |since_epoch| since_epoch.as_nanos());
+            (metadata.len(), modified)
+        })
+        .unwrap_or_default();
+    format!("{}:{}", len, modified)
+}
+
+/// Whether the Hub answered 404, i.e. the repo has no such file.
+fn is_not_found(error: &ApiError) -> bool {
+    match error {
+        ApiError::RequestError(error) => matches!(**error, ureq::Error::Status(404, _)),
+        ApiError::TooManyRetries(error) => is_not_found(error),
+        _ => false,
+    }
+}
+
+/// Output length of the model in a local folder, from `hidden_size` in its
+/// `config.json` and its sentence-transformers modules.
+fn local_embedding_dim(local_path: &Path) -> Option<usize> {
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(local_path.join("config.json")).ok()?)
+            .ok()?;
+    let hidden_size = config.get("hidden_size")?.as_u64()? as usize;
+    sentence_transformers::local_output_dim(local_path, hidden_size)
+}
+
+impl ModelType {
+    fn device(&self) -> &Device {
+        match &self {
             Self::Bert(device) => device,
             Self::Jina(device) => device,
+            Self::MsMarco(device) => device,
+            Self::Custom(_, device) => device,
+        }
+    }
+
+    /// Length of a preset's vectors, known up front so SQL signatures can be
+    /// declared without fetching anything. A preset pointed at a local folder
+    /// through its `*_MODEL_FOLDER` variable reads it from that folder's
+    /// configs instead, and is `None` when they can't be read; custom models
+    /// only find out once they are loaded.
+    pub fn embedding_dim(&self) -> Option<usize> {
+        let preset_dim = match &self {
+            Self::Bert(_) => 384,
+            Self::Jina(_) => 768,
+            Self::MsMarco(_) => 384,
+            Self::Custom(..) => return None,
         };
+        match self.source() {
+            ModelSource::Hub { .. } => Some(preset_dim),
+            ModelSource::Local(local_path) => local_embedding_dim(&local_path),
+        }
+    }
 
+    fn source(&self) -> ModelSource {
+        let (folder_variable, model_id) = match &self {
+            Self::Bert(_) => (
+                "BERT_MODEL_FOLDER",
+                "sentence-transformers/all-MiniLM-L6-v2",
+            ),
+            Self::Jina(_) => ("JINA_MODEL_FOLDER", "jinaai/jina-embeddings-v2-base-en"),
+            Self::MsMarco(_) => (
+                "MS_MARCO_MODEL_FOLDER",
+                "cross-encoder/ms-marco-MiniLM-L-6-v2",
+            ),
+            Self::Custom(source, _) => return source.clone(),
+        };
         // Try to load from local path first, fall back to HuggingFace Hub
-        let (tokenizer_filename, weights_filename) = if let Some(local_path) = self.get_local_model_path() {
-            self.load_from_local(&local_path)?
-        } else {
-            self.load_from_hub()?
+        match std::env::var(folder_variable) {
+            Ok(local_path) => ModelSource::Local(PathBuf::from(local_path)),
+            Err(_) => ModelSource::Hub {
+                repo_id: model_id.to_string(),
+                revision: None,
+            },
+        }
+    }
+
+    pub fn build_text_embedder(&self) -> Result<TextEmbedder, EmbeddingError> {
+        let device = self.device();
+        let source = self.source();
+        let files = source.files()?;
+
+        let architecture = Architecture::from_config_file(&files.config)?;
+        let tokenizer = TextTokenizer::new(
+            Tokenizer::from_file(&files.tokenizer)?,
+            architecture.max_position_embeddings(),
+        )?;
+
+        let vb = unsafe {
+            VarBuilder::from_mmaped_safetensors(
+                std::slice::from_ref(&files.weights),
+                DTYPE,
+                device,
+            )?
         };
+        let model = architecture.load(vb.clone())?;
+        let classification_head = architecture.classification_head(vb)?;
+        // every file the pipeline reads goes into the fingerprint too
+        let pipeline_files = RefCell::new(Vec::new());
+        let pipeline = SentencePipeline::load(
+            &|name| {
+                let file = source.optional_file(name, files.cached)?;
+                pipeline_files.borrow_mut().extend(file.clone());
+                Ok(file)
+            },
+            device,
+        )?;
+        let mut fingerprint_files = vec![files.weights, files.config, files.tokenizer];
+        fingerprint_files.extend(pipeline_files.into_inner());
+        let fingerprint = source.fingerprint(&fingerprint_files);
+
+        Ok(TextEmbedder {
+            model,
+            tokenizer,
+            pipeline,
+            classification_head,
+            fingerprint,
+        })
+    }
+}
+
+/// Paths of the files a model is built from, local or downloaded.
+struct ModelFiles {
+    config: PathBuf,
+    tokenizer: PathBuf,
+    weights: PathBuf,
+    /// Whether all three were on disk before loading, so nothing was downloaded.
+    cached: bool,
+}
+
+/// Network picked from a checkpoint's `config.json`, with its deserialized config.
+enum Architecture {
+    Bert(Config),
+    /// A BERT cross-encoder and its number of labels.
+    BertClassifier(Config, usize),
+    Jina(JinaConfig),
+}
 
-        let tokenizer = Tokenizer::from_file(tokenizer_filename)?;
+impl Architecture {
+    fn from_config_file(path: &Path) -> Result<Self, EmbeddingError> {
+        let config: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+
+        let model_type = config
+            .get("model_type")
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let architectures = config
+            .get("architectures")
+            .and_then(|value| value.as_array())
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.as_str().map(str::to_string))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let position_embedding_type = config
+            .get("position_embedding_type")
+            .and_then(|value| value.as_str());
+
+        // Jina checkpoints also declare `model_type: "bert"`, so look for them first
+        if architectures
+            .iter()
+            .any(|name| name.starts_with("JinaBert"))
+            || position_embedding_type == Some("alibi")
+        {
+            Ok(Self::Jina(serde_json::from_value(config)?))
+        } else if model_type == "bert" || architectures.iter().any(|name| name.starts_with("Bert"))
+        {
+            if architectures
+                .iter()
+                .any(|name| name.ends_with("ForSequenceClassification"))
+            {
+                let num_labels = config
+                    .get("id2label")
+                    .and_then(|labels| labels.as_object())
+                    .map_or(1, |labels| labels.len());
+                Ok(Self::BertClassifier(
+                    serde_json::from_value(config)?,
+                    num_labels,
+                ))
+            } else {
+                Ok(Self::Bert(serde_json::from_value(config)?))
+            }
+        } else {
+            Err(EmbeddingError::ModelTypeError(format!(
+                "Unsupported architecture (model_type: '{}', architectures: {:?})",
+                model_type, architectures
+            )))
+        }
+    }
 
-        let vb =
-            unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, device)? };
+    /// Longest input, special tokens included, the position embeddings cover.
+    fn max_position_embeddings(&self) -> usize {
+        match self {
+            Self::Bert(config) | Self::BertClassifier(config, _) => config.max_position_embeddings,
+            Self::Jina(config) => config.max_position_embeddings,
+        }
+    }
 
-        let model: Box<dyn EmbedModel> = match &self {
-            Self::Bert(_) => Box::new(self.get_bert_model(vb)?),
-            Self::Jina(_) => Box::new(self.get_jina_model(vb)?),
+    fn load(&self, vb: VarBuilder) -> Result<Box<dyn EmbedModel>, EmbeddingError> {
+        let model: Box<dyn EmbedModel> = match self {
+            Self::Bert(config) | Self::BertClassifier(config, _) => {
+                Box::new(BertModel::load(vb, config)?)
+            }
+            Self::Jina(config) => Box::new(JinaModel::load(vb, config)?),
         };
+        Ok(model)
+    }
+
+    fn classification_head(
+        &self,
+        vb: VarBuilder,
+    ) -> Result<Option<ClassificationHead>, EmbeddingError> {
+        match self {
+            Self::BertClassifier(config, num_labels) => Ok(Some(ClassificationHead::load(
+                vb,
+                config.hidden_size,
+                *num_labels,
+            )?)),
+            Self::Bert(_) | Self::Jina(_) => Ok(None),
+        }
+    }
+}
 
-        Ok(TextEmbedder { model, tokenizer })
+/// Overrides of a model's own pooling, normalization and truncation; `None`
+/// keeps what the model declares.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EmbedOptions {
+    pub pooling: Option<Pooling>,
+    pub normalize: Option<bool>,
+    /// Side cut from inputs over the token limit, `Right` by default.
+    pub truncation: Option<Truncation>,
+    /// Token limit including special tokens, capped at the model's position limit.
+    pub max_tokens: Option<usize>,
+}
+
+impl EmbedOptions {
+    /// Fills whatever `self` leaves unset from `defaults`.
+    pub fn or(&self, defaults: &EmbedOptions) -> EmbedOptions {
+        EmbedOptions {
+            pooling: self.pooling.or(defaults.pooling),
+            normalize: self.normalize.or(defaults.normalize),
+            truncation: self.truncation.or(defaults.truncation),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+        }
     }
 }
 
+/// Embedding only reads the model and tokenizer, so one embedder can serve
+/// every DuckDB thread at once.
 pub trait Embed {
     fn embed(
-        &mut self,
+        &self,
+        column: Vec<String>,
+        max_batch_tokens: usize,
+    ) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        self.embed_with_options(column, max_batch_tokens, &EmbedOptions::default())
+    }
+
+    /// Embeds `column`, batching texts of similar length together so that each
+    /// batch holds at most `max_batch_tokens` tokens, padding included.
+    fn embed_with_options(
+        &self,
         column: Vec<String>,
-        batch_size: usize,
+        max_batch_tokens: usize,
+        options: &EmbedOptions,
     ) -> Result<Vec<Vec<f32>>, EmbeddingError>;
 }
 
@@ -204,69 +564,308 @@ impl EmbedModel for JinaModel {
     }
 }
 
-impl Embed for TextEmbedder {
-    fn embed(
-        &mut self,
+/// A span of a text, `[start_char, end_char)` in characters, with its late-chunking vector.
+#[derive(Clone, Debug)]
+pub struct LateChunk {
+    pub start_char: usize,
+    pub end_char: usize,
+    pub embedding: Vec<f32>,
+}
+
+impl TextEmbedder {
+    /// Tokenizes `column` the way `embed_with_options` would, truncating every
+    /// input over the token limit instead of overflowing the position embeddings.
+    pub fn tokenize(
+        &self,
+        column: Vec<String>,
+        options: &EmbedOptions,
+    ) -> Result<Vec<TokenizedText>, EmbeddingError> {
+        self.tokenizer.tokenize(
+            column,
+            options.truncation.unwrap_or(Truncation::Right),
+            self.tokenizer.max_tokens(options.max_tokens),
+        )
+    }
+
+    /// Tokens of text a single input can hold under `max_tokens`, special tokens excluded.
+    pub fn text_budget(&self, max_tokens: Option<usize>) -> usize {
+        self.tokenizer.text_budget(max_tokens)
+    }
+
+    /// Token ids and token strings of each text, special tokens included and
+    /// nothing truncated.
+    pub fn encode_full(&self, column: Vec<String>) -> Result<Vec<TokenPieces>, EmbeddingError> {
+        self.tokenizer.encode_full(column)
+    }
+
+    /// Text of `ids`, leaving out special tokens.
+    pub fn decode(&self, ids: &[u32]) -> Result<String, EmbeddingError> {
+        self.tokenizer.decode(ids)
+    }
+
+    /// `(start, end)` character span of every token of `text`.
+    pub fn token_offsets(&self, text: &str) -> Result<Vec<(usize, usize)>, EmbeddingError> {
+        self.tokenizer.token_offsets(text)
+    }
+
+    /// Embeds texts of any length: each one is cut into overlapping windows of
+    /// `window` tokens starting `stride` tokens apart, every window goes through
+    /// the model, and the window vectors are merged with `combine`.
+    pub fn embed_long(
+        &self,
         column: Vec<String>,
-        batch_size: usize,
+        window: usize,
+        stride: usize,
+        combine: WindowCombine,
+        max_batch_tokens: usize,
+        options: &EmbedOptions,
     ) -> Result<Vec<Vec<f32>>, EmbeddingError> {
-        let device = self.model.device();
+        let windows = self.tokenizer.windows(column, window, stride)?;
+        let flat = windows.iter().flatten().collect::<Vec<_>>();
+        let mut window_embeddings = self
+            .embed_tokenized(&flat, max_batch_tokens, options)?
+            .into_iter();
+
+        let normalize = self.pipeline.normalizes(options);
+        Ok(windows
+            .iter()
+            .map(|text_windows| {
+                let vectors = window_embeddings
+                    .by_ref()
+                    .take(text_windows.len())
+                    .collect::<Vec<_>>();
+                combine.apply(&vectors, normalize)
+            })
+            .collect())
+    }
 
-        // padding
-        if let Some(pp) = self.tokenizer.get_padding_mut() {
-            pp.strategy = tokenizers::PaddingStrategy::BatchLongest
-        } else {
-            let pp = PaddingParams {
-                strategy: tokenizers::PaddingStrategy::BatchLongest,
-                ..Default::default()
-            };
-            self.tokenizer.with_padding(Some(pp));
+    /// Late chunking: runs the whole text through the model once, then mean-pools
+    /// the token states of each span of `chunk_size` tokens, consecutive spans
+    /// sharing `overlap` tokens. Every chunk vector thus carries context from the
+    /// rest of the document. Text past the token limit is dropped.
+    pub fn embed_late_chunks(
+        &self,
+        text: &str,
+        chunk_size: usize,
+        overlap: usize,
+        options: &EmbedOptions,
+    ) -> Result<Vec<LateChunk>, EmbeddingError> {
+        if chunk_size == 0 || overlap >= chunk_size {
+            return Err(EmbeddingError::InvalidArgument(format!(
+                "chunk_size must be positive and larger than overlap, got chunk_size {} and overlap {}",
+                chunk_size, overlap
+            )));
+        }
+        let (tokenized, offsets) = self
+            .tokenizer
+            .tokenize_with_offsets(text, self.tokenizer.max_tokens(options.max_tokens))?;
+        // positions of real tokens, skipping [CLS], [SEP] and the like
+        let content = offsets
+            .iter()
+            .enumerate()
+            .filter_map(|(position, span)| span.map(|_| position))
+            .collect::<Vec<_>>();
+        if content.is_empty() {
+            return Ok(Vec::new());
         }
 
-        // chunk based approach
-        let mut all_embeddings = Vec::with_capacity(column.len());
-
-        for chunk in column.chunks(batch_size) {
-            let tokens = self.tokenizer.encode_batch(chunk.to_vec(), true)?;
+        let mut spans = Vec::new();
+        let mut start = 0;
+        loop {
+            let end = (start + chunk_size).min(content.len());
+            spans.push(start..end);
+            if end == content.len() {
+                break;
+            }
+            start += chunk_size - overlap;
+        }
 
-            let token_ids = tokens
-                .iter()
-                .map(|tokens| {
-                    let tokens = tokens.get_ids().to_vec();
-                    Ok(Tensor::new(tokens.as_slice(), device)?)
+        let device = self.model.device();
+        let (token_ids, token_type_ids, attention_mask) =
+            self.tokenizer.batch_tensors(&[&tokenized], device)?;
+        let token_states =
+            self.model
+                .forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
+
+        // one mask row per span over the single sequence's token states
+        let seq_len = tokenized.ids.len();
+        let mut span_mask = vec![0f32; spans.len() * seq_len];
+        for (chunk, span) in spans.iter().enumerate() {
+            for &position in &content[span.clone()] {
+                span_mask[chunk * seq_len + position] = 1.0;
+            }
+        }
+        let span_mask = Tensor::from_vec(span_mask, (spans.len(), seq_len), device)?;
+        let pooled = Pooling::Mean.apply(&token_states, &span_mask)?;
+        let embeddings = self.pipeline.project(&pooled, options)?.to_vec2::<f32>()?;
+
+        Ok(spans
+            .into_iter()
+            .zip(embeddings)
+            .filter_map(|(span, embedding)| {
+                let (start_char, _) = offsets[content[span.start]]?;
+                let (_, end_char) = offsets[content[span.end - 1]]?;
+                Some(LateChunk {
+                    start_char,
+                    end_char,
+                    embedding,
                 })
-                .collect::<Result<Vec<_>, EmbeddingError>>()?;
+            })
+            .collect())
+    }
+
+    /// Identifies the checkpoint the embedder was built from, for cache keys.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
 
-            let attention_mask = tokens
+    /// Cross-encoder relevance of each `(query, document)` pair: both texts are
+    /// encoded together, each with its own segment id, and the classification
+    /// head's logit is returned (higher is more relevant). Pairs are batched by
+    /// length like [`Embed::embed_with_options`].
+    pub fn score_pairs(
+        &self,
+        pairs: Vec<(String, String)>,
+        max_batch_tokens: usize,
+        options: &EmbedOptions,
+    ) -> Result<Vec<f32>, EmbeddingError> {
+        let Some(head) = &self.classification_head else {
+            return Err(EmbeddingError::ModelTypeError(
+                "Model has no classification head, use a cross-encoder such as cross-encoder/ms-marco-MiniLM-L-6-v2"
+                    .to_string(),
+            ));
+        };
+        let device = self.model.device();
+        let tokenized = self
+            .tokenizer
+            .tokenize_pairs(pairs, self.tokenizer.max_tokens(options.max_tokens))?;
+
+        let lengths = tokenized
+            .iter()
+            .map(|text| text.ids.len())
+            .collect::<Vec<_>>();
+        let mut scores = vec![0.0; tokenized.len()];
+        for batch in length_batches(&lengths, max_batch_tokens) {
+            let chunk = batch
                 .iter()
-                .map(|tokens| {
-                    let tokens = tokens.get_attention_mask().to_vec();
-                    Ok(Tensor::new(tokens.as_slice(), device)?)
-                })
-                .collect::<Result<Vec<_>, EmbeddingError>>()?;
+                .map(|&position| &tokenized[position])
+                .collect::<Vec<_>>();
+            let (token_ids, token_type_ids, attention_mask) =
+                self.tokenizer.batch_tensors(&chunk, device)?;
+            let token_states =
+                self.model
+                    .forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
+            for (position, score) in batch.into_iter().zip(head.scores(&token_states)?) {
+                scores[position] = score;
+            }
+        }
+        Ok(scores)
+    }
 
-            let token_ids = Tensor::stack(&token_ids, 0)?;
-            let attention_mask = Tensor::stack(&attention_mask, 0)?;
-            let token_type_ids = token_ids.zeros_like()?;
+    /// Runs already tokenized inputs through the model in length-sorted batches
+    /// of at most `max_batch_tokens` padded tokens, returning the vectors in
+    /// input order.
+    fn embed_tokenized(
+        &self,
+        tokenized: &[&TokenizedText],
+        max_batch_tokens: usize,
+        options: &EmbedOptions,
+    ) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        let device = self.model.device();
+        let lengths = tokenized
+            .iter()
+            .map(|text| text.ids.len())
+            .collect::<Vec<_>>();
+        let mut all_embeddings = vec![Vec::new(); tokenized.len()];
+
+        for batch in length_batches(&lengths, max_batch_tokens) {
+            let chunk = batch
+                .iter()
+                .map(|&position| tokenized[position])
+                .collect::<Vec<_>>();
+            let (token_ids, token_type_ids, attention_mask) =
+                self.tokenizer.batch_tensors(&chunk, device)?;
 
             let embeddings =
                 self.model
                     .forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
 
             let attention_mask = attention_mask.to_dtype(candle_core::DType::F32)?;
-            let masked_embeddings = embeddings.broadcast_mul(&attention_mask.unsqueeze(2)?)?;
-            let sum_embeddings = masked_embeddings.sum(1)?;
-            let real_token_counts = attention_mask.sum(1)?.maximum(1e-8)?;
-            let mean_embeddings = sum_embeddings.broadcast_div(&real_token_counts.unsqueeze(1)?)?;
-            let normalized_embeddings = normalize_l2(&mean_embeddings)?;
-
-            let chunk_embeddings = normalized_embeddings.to_vec2()?;
-            all_embeddings.extend(chunk_embeddings);
+            let sentence_embeddings = self.pipeline.apply(&embeddings, &attention_mask, options)?;
+
+            // scatter back to the input positions
+            for (position, embedding) in batch.into_iter().zip(sentence_embeddings.to_vec2()?) {
+                all_embeddings[position] = embedding;
+            }
         }
         Ok(all_embeddings)
     }
 }
 
+impl Embed for TextEmbedder {
+    fn embed_with_options(
+        &self,
+        column: Vec<String>,
+        max_batch_tokens: usize,
+        options: &EmbedOptions,
+    ) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        // repeated texts are tokenized and embedded once, then copied back out
+        let (distinct, positions) = dedupe(column);
+        let tokenized = self.tokenize(distinct, options)?;
+        let embeddings = self.embed_tokenized(
+            &tokenized.iter().collect::<Vec<_>>(),
+            max_batch_tokens,
+            options,
+        )?;
+        Ok(positions
+            .into_iter()
+            .map(|position| embeddings[position].clone())
+            .collect())
+    }
+}
+
+/// The distinct texts of `column` in first-seen order, and for every text of
+/// `column` the position of its copy among them.
+fn dedupe(column: Vec<String>) -> (Vec<String>, Vec<usize>) {
+    let mut seen = HashMap::with_capacity(column.len());
+    let mut distinct = Vec::new();
+    let positions = column
+        .into_iter()
+        .map(|text| {
+            *seen.entry(text).or_insert_with_key(|text| {
+                distinct.push(text.clone());
+                distinct.len() - 1
+            })
+        })
+        .collect();
+    (distinct, positions)
+}
+
 fn normalize_l2(v: &Tensor) -> Result<Tensor, EmbeddingError> {
     Ok(v.broadcast_div(&v.sqr()?.sum_keepdim(1)?.sqrt()?)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::dedupe;
+
+    #[test]
+    fn dedupe_keeps_first_seen_order_and_maps_every_row() {
+        let column = ["ok", "failed", "ok", "", "failed", "ok"]
+            .map(str::to_string)
+            .to_vec();
+        let (distinct, positions) = dedupe(column.clone());
+        assert_eq!(distinct, ["ok", "failed", ""]);
+        assert_eq!(positions, [0, 1, 0, 2, 1, 0]);
+        for (text, position) in column.iter().zip(positions) {
+            assert_eq!(&distinct[position], text);
+        }
+    }
+
+    #[test]
+    fn dedupe_of_nothing_is_empty() {
+        let (distinct, positions) = dedupe(Vec::new());
+        assert!(distinct.is_empty());
+        assert!(positions.is_empty());
+    }
+}
@@ -0,0 +1,32 @@
+use super::EmbeddingError;
+use candle_core::{IndexOp, Tensor};
+use candle_nn::{linear, Linear, Module, VarBuilder};
+
+/// The sequence-classification head of a `BertForSequenceClassification`
+/// checkpoint: the pooler (dense + tanh over `[CLS]`) and the classifier.
+pub struct ClassificationHead {
+    pooler: Linear,
+    classifier: Linear,
+}
+
+impl ClassificationHead {
+    /// `vb` is the checkpoint root, holding `bert.pooler.dense` and `classifier`.
+    pub fn load(
+        vb: VarBuilder,
+        hidden_size: usize,
+        num_labels: usize,
+    ) -> Result<Self, EmbeddingError> {
+        let pooler = linear(hidden_size, hidden_size, vb.pp("bert.pooler.dense"))?;
+        let classifier = linear(hidden_size, num_labels, vb.pp("classifier"))?;
+        Ok(Self { pooler, classifier })
+    }
+
+    /// Turns `(batch, seq_len, hidden)` token states into one score per row:
+    /// the first label's logit, the relevance of a cross-encoder pair.
+    pub fn scores(&self, token_states: &Tensor) -> Result<Vec<f32>, EmbeddingError> {
+        let cls = token_states.i((.., 0))?;
+        let pooled = self.pooler.forward(&cls)?.tanh()?;
+        let logits = self.classifier.forward(&pooled)?;
+        Ok(logits.i((.., 0))?.to_vec1::<f32>()?)
+    }
+}
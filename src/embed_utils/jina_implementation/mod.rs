@@ -1,4 +1,4 @@
-use candle_core::{DType, Device, IndexOp, Result, Tensor, D};
+use candle_core::{DType, Device, Result, Tensor, D};
 use candle_nn::ops::softmax_last_dim;
 use candle_nn::{
     embedding, layer_norm, linear, linear_no_bias, Embedding, LayerNorm, Linear, Module, VarBuilder,
@@ -6,12 +6,13 @@ use candle_nn::{
 use serde::Deserialize;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PositionEmbeddingType {
     Alibi,
     Absolute,
 }
 
-// Create a config struct with its impl based on https://huggingface.co/jinaai/jina-embeddings-v2-base-en/blob/main/config.json
+// Deserialized from config.json, e.g. https://huggingface.co/jinaai/jina-embeddings-v2-base-en/blob/main/config.json
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Config {
     pub vocab_size: usize,
@@ -29,23 +30,6 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn v2_base() -> Self {
-        Self {
-            vocab_size: 30528,
-            hidden_size: 768,
-            num_hidden_layers: 12,
-            num_attention_heads: 12,
-            intermediate_size: 3072,
-            hidden_act: candle_nn::Activation::Gelu,
-            max_position_embeddings: 8192,
-            type_vocab_size: 2,
-            initializer_range: 0.02,
-            layer_norm_eps: 1e-12,
-            pad_token_id: 0,
-            position_embedding_type: PositionEmbeddingType::Alibi,
-        }
-    }
-
     // If we want a different config, not sure if we're gonna use this
     #[allow(clippy::too_many_arguments, dead_code)]
     pub fn new(
@@ -293,16 +277,10 @@ impl BertLayer {
     }
 }
 
-fn build_alibi_bias(config: &Config) -> Result<Tensor> {
+/// Per-head ALiBi slopes, shaped `(1, num_heads, 1, 1)` so they broadcast
+/// over a `(seq_len, seq_len)` distance matrix.
+fn build_alibi_slopes(config: &Config, device: &Device) -> Result<Tensor> {
     let n_heads = config.num_attention_heads;
-    let seq_len = config.max_position_embeddings;
-    let alibi_bias = Tensor::arange(0, seq_len as i64, &Device::Cpu)?.to_dtype(DType::F32)?;
-    let alibi_bias = {
-        let a1 = alibi_bias.reshape((1, seq_len))?;
-        let a2 = alibi_bias.reshape((seq_len, 1))?;
-        a1.broadcast_sub(&a2)?.abs()?.broadcast_left(n_heads)?
-    };
-
     let mut n_heads2 = 1;
     while n_heads2 < n_heads {
         n_heads2 *= 2
@@ -322,13 +300,26 @@ fn build_alibi_bias(config: &Config) -> Result<Tensor> {
             .cloned()
             .collect::<Vec<f32>>()
     };
-    let slopes = Tensor::new(slopes, &Device::Cpu)?.reshape((1, (), 1, 1))?;
-    alibi_bias.to_dtype(DType::F32)?.broadcast_mul(&slopes)
+    Tensor::new(slopes, device)?.reshape((1, (), 1, 1))
+}
+
+/// ALiBi bias for the actual sequence length of a batch, `(1, num_heads, seq_len, seq_len)`.
+/// Built per forward pass: a full `max_position_embeddings²` table would cost
+/// gigabytes for 8192-token models while a batch only ever needs `seq_len²`.
+fn build_alibi_bias(slopes: &Tensor, seq_len: usize) -> Result<Tensor> {
+    let n_heads = slopes.dim(1)?;
+    let alibi_bias = Tensor::arange(0, seq_len as i64, slopes.device())?.to_dtype(DType::F32)?;
+    let alibi_bias = {
+        let a1 = alibi_bias.reshape((1, seq_len))?;
+        let a2 = alibi_bias.reshape((seq_len, 1))?;
+        a1.broadcast_sub(&a2)?.abs()?.broadcast_left(n_heads)?
+    };
+    alibi_bias.broadcast_mul(slopes)
 }
 
 #[derive(Clone, Debug)]
 struct BertEncoder {
-    alibi: Tensor,
+    alibi_slopes: Tensor,
     layers: Vec<BertLayer>,
 }
 
@@ -337,15 +328,30 @@ impl BertEncoder {
         let layers = (0..config.num_hidden_layers)
             .map(|index| BertLayer::new(vb.pp(format!("layer.{index}")), config))
             .collect::<Result<Vec<_>>>()?;
-        let alibi = build_alibi_bias(config)?.to_device(vb.device())?;
-        Ok(Self { alibi, layers })
+        let alibi_slopes = build_alibi_slopes(config, vb.device())?;
+        Ok(Self {
+            alibi_slopes,
+            layers,
+        })
     }
-}
 
-impl Module for BertEncoder {
-    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+    /// `attention_mask` is `(batch, seq_len)` with 1 for real tokens and 0 for padding.
+    fn forward(&self, xs: &Tensor, attention_mask: Option<&Tensor>) -> Result<Tensor> {
         let seq_len = xs.dim(1)?;
-        let alibi_bias = self.alibi.i((.., .., ..seq_len, ..seq_len))?;
+        let alibi_bias = build_alibi_bias(&self.alibi_slopes, seq_len)?;
+        // Fold the padding mask into the ALiBi bias so no token attends to padding,
+        // otherwise a sentence's vector depends on what else shares its batch
+        let alibi_bias = match attention_mask {
+            Some(mask) => {
+                let mask_bias = mask
+                    .to_dtype(DType::F32)?
+                    .affine(-(f32::MIN as f64), f32::MIN as f64)?
+                    .unsqueeze(1)?
+                    .unsqueeze(1)?;
+                alibi_bias.broadcast_add(&mask_bias)?
+            }
+            None => alibi_bias,
+        };
         let mut xs = xs.clone();
         for layer in self.layers.iter() {
             xs = layer.forward(&xs, &alibi_bias)?
@@ -375,10 +381,10 @@ impl JinaModel {
         &self,
         input_ids: &Tensor,
         _token_type_ids: &Tensor,
-        _attention_mask: Option<&Tensor>,
+        attention_mask: Option<&Tensor>,
     ) -> Result<Tensor> {
         let embedding_output = self.embeddings.forward(input_ids)?;
-        let sequence_output = self.encoder.forward(&embedding_output)?;
+        let sequence_output = self.encoder.forward(&embedding_output, attention_mask)?;
         Ok(sequence_output)
     }
 }
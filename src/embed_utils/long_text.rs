@@ -0,0 +1,58 @@
+use super::EmbeddingError;
+use std::str::FromStr;
+
+/// How the vectors of a long text's windows are merged into one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowCombine {
+    Mean,
+    Max,
+}
+
+impl FromStr for WindowCombine {
+    type Err = EmbeddingError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "mean" => Ok(Self::Mean),
+            "max" => Ok(Self::Max),
+            _ => Err(EmbeddingError::InvalidArgument(format!(
+                "Unknown combine '{}', expected one of: mean, max",
+                name
+            ))),
+        }
+    }
+}
+
+impl WindowCombine {
+    /// Merges same-length window vectors element-wise; `normalize` rescales the
+    /// result to unit length, since a mean of unit vectors is shorter than one.
+    pub fn apply(&self, windows: &[Vec<f32>], normalize: bool) -> Vec<f32> {
+        let Some((first, rest)) = windows.split_first() else {
+            return Vec::new();
+        };
+        let mut combined = first.clone();
+        for window in rest {
+            for (value, other) in combined.iter_mut().zip(window) {
+                match self {
+                    Self::Mean => *value += other,
+                    Self::Max => *value = value.max(*other),
+                }
+            }
+        }
+        if *self == Self::Mean {
+            let count = windows.len() as f32;
+            combined.iter_mut().for_each(|value| *value /= count);
+        }
+        if normalize {
+            let norm = combined
+                .iter()
+                .map(|value| value * value)
+                .sum::<f32>()
+                .sqrt();
+            if norm > 0.0 {
+                combined.iter_mut().for_each(|value| *value /= norm);
+            }
+        }
+        combined
+    }
+}
@@ -0,0 +1,91 @@
+use super::EmbeddingError;
+use candle_core::{IndexOp, Tensor};
+use std::str::FromStr;
+
+/// How token states are reduced to one sentence vector. Names follow the
+/// `pooling_mode_*` keys of a sentence-transformers `1_Pooling/config.json`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pooling {
+    Cls,
+    Mean,
+    Max,
+    MeanSqrtLen,
+    WeightedMean,
+    LastToken,
+}
+
+impl FromStr for Pooling {
+    type Err = EmbeddingError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "cls" => Ok(Self::Cls),
+            "mean" => Ok(Self::Mean),
+            "max" => Ok(Self::Max),
+            "mean_sqrt_len" => Ok(Self::MeanSqrtLen),
+            "weighted_mean" => Ok(Self::WeightedMean),
+            "last_token" => Ok(Self::LastToken),
+            _ => Err(EmbeddingError::InvalidArgument(format!(
+                "Unknown pooling '{}', expected one of: cls, mean, max, mean_sqrt_len, weighted_mean, last_token",
+                name
+            ))),
+        }
+    }
+}
+
+impl Pooling {
+    /// Pools `(batch, seq_len, hidden)` token states into `(batch, hidden)`.
+    /// `attention_mask` is `(batch, seq_len)` as f32, 0 marking padding.
+    pub fn apply(
+        &self,
+        embeddings: &Tensor,
+        attention_mask: &Tensor,
+    ) -> Result<Tensor, EmbeddingError> {
+        let mask = attention_mask.unsqueeze(2)?;
+        let real_token_counts = attention_mask.sum(1)?.maximum(1e-8)?.unsqueeze(1)?;
+
+        let pooled = match self {
+            Self::Cls => embeddings.i((.., 0))?,
+            Self::Mean => embeddings
+                .broadcast_mul(&mask)?
+                .sum(1)?
+                .broadcast_div(&real_token_counts)?,
+            Self::MeanSqrtLen => embeddings
+                .broadcast_mul(&mask)?
+                .sum(1)?
+                .broadcast_div(&real_token_counts.sqrt()?)?,
+            Self::Max => {
+                // push padding far below any real activation before taking the max
+                let padding_bias = mask.affine(1e9, -1e9)?;
+                embeddings.broadcast_add(&padding_bias)?.max(1)?
+            }
+            Self::WeightedMean => {
+                // later tokens weigh more: position i (1-based) gets weight i
+                let seq_len = attention_mask.dim(1)?;
+                let positions =
+                    Tensor::arange(1f32, (seq_len + 1) as f32, attention_mask.device())?
+                        .unsqueeze(0)?;
+                let weights = attention_mask.broadcast_mul(&positions)?;
+                let weight_sums = weights.sum(1)?.maximum(1e-8)?.unsqueeze(1)?;
+                embeddings
+                    .broadcast_mul(&weights.unsqueeze(2)?)?
+                    .sum(1)?
+                    .broadcast_div(&weight_sums)?
+            }
+            Self::LastToken => {
+                // batches are right-padded, so the last real token sits at count - 1
+                let lengths = attention_mask.sum(1)?.to_vec1::<f32>()?;
+                let rows = lengths
+                    .iter()
+                    .enumerate()
+                    .map(|(row, &length)| {
+                        let last = (length as usize).saturating_sub(1);
+                        Ok(embeddings.i((row, last))?)
+                    })
+                    .collect::<Result<Vec<_>, EmbeddingError>>()?;
+                Tensor::stack(&rows, 0)?
+            }
+        };
+        Ok(pooled)
+    }
+}
@@ -0,0 +1,255 @@
+use super::pooling::Pooling;
+use super::{normalize_l2, EmbedOptions, EmbeddingError};
+use candle_core::{Device, Tensor};
+use candle_nn::{linear, linear_no_bias, Linear, Module, VarBuilder};
+use candle_transformers::models::bert::DTYPE;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+// https://sbert.net/docs/package_reference/sentence_transformer/models.html
+const POOLING_MODULE: &str = "sentence_transformers.models.Pooling";
+const DENSE_MODULE: &str = "sentence_transformers.models.Dense";
+const NORMALIZE_MODULE: &str = "sentence_transformers.models.Normalize";
+
+#[derive(Debug, Deserialize)]
+struct ModuleEntry {
+    path: String,
+    #[serde(rename = "type")]
+    module_type: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PoolingConfig {
+    #[serde(default)]
+    pooling_mode_cls_token: bool,
+    #[serde(default)]
+    pooling_mode_max_tokens: bool,
+    #[serde(default)]
+    pooling_mode_mean_tokens: bool,
+    #[serde(default)]
+    pooling_mode_mean_sqrt_len_tokens: bool,
+    #[serde(default)]
+    pooling_mode_weightedmean_tokens: bool,
+    #[serde(default)]
+    pooling_mode_lasttoken: bool,
+}
+
+impl PoolingConfig {
+    /// Enabled modes, in the order sentence-transformers concatenates them.
+    fn modes(&self) -> Vec<Pooling> {
+        [
+            (self.pooling_mode_cls_token, Pooling::Cls),
+            (self.pooling_mode_max_tokens, Pooling::Max),
+            (self.pooling_mode_mean_tokens, Pooling::Mean),
+            (self.pooling_mode_mean_sqrt_len_tokens, Pooling::MeanSqrtLen),
+            (self.pooling_mode_weightedmean_tokens, Pooling::WeightedMean),
+            (self.pooling_mode_lasttoken, Pooling::LastToken),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, mode)| enabled.then_some(mode))
+        .collect()
+    }
+}
+
+fn default_bias() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct DenseConfig {
+    in_features: usize,
+    out_features: usize,
+    #[serde(default = "default_bias")]
+    bias: bool,
+    activation_function: String,
+}
+
+#[derive(Clone, Debug)]
+enum DenseActivation {
+    Identity,
+    Tanh,
+}
+
+/// A `Dense` projection module, e.g. `2_Dense`.
+#[derive(Clone, Debug)]
+struct Dense {
+    linear: Linear,
+    activation: DenseActivation,
+}
+
+impl Dense {
+    fn load(
+        config: DenseConfig,
+        weights: PathBuf,
+        device: &Device,
+    ) -> Result<Self, EmbeddingError> {
+        let activation = match config.activation_function.rsplit('.').next() {
+            Some("Identity") => DenseActivation::Identity,
+            Some("Tanh") => DenseActivation::Tanh,
+            _ => {
+                return Err(EmbeddingError::ModelTypeError(format!(
+                    "Unsupported Dense activation '{}'",
+                    config.activation_function
+                )))
+            }
+        };
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, device)? };
+        let linear = if config.bias {
+            linear(config.in_features, config.out_features, vb.pp("linear"))?
+        } else {
+            linear_no_bias(config.in_features, config.out_features, vb.pp("linear"))?
+        };
+        Ok(Self { linear, activation })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor, EmbeddingError> {
+        let xs = self.linear.forward(xs)?;
+        Ok(match self.activation {
+            DenseActivation::Identity => xs,
+            DenseActivation::Tanh => xs.tanh()?,
+        })
+    }
+}
+
+/// Everything a sentence-transformers model does after the transformer:
+/// pooling, optional Dense projections and optional L2 normalization.
+#[derive(Clone, Debug)]
+pub struct SentencePipeline {
+    pooling: Vec<Pooling>,
+    dense: Vec<Dense>,
+    normalize: bool,
+}
+
+impl Default for SentencePipeline {
+    /// Masked mean pooling then L2 normalization, used when a model ships no `modules.json`.
+    fn default() -> Self {
+        Self {
+            pooling: vec![Pooling::Mean],
+            dense: Vec::new(),
+            normalize: true,
+        }
+    }
+}
+
+impl SentencePipeline {
+    /// Builds the pipeline declared in `modules.json`. `fetch` resolves a path
+    /// relative to the model root, returning `None` when the file doesn't exist.
+    pub fn load(
+        fetch: &dyn Fn(&str) -> Result<Option<PathBuf>, EmbeddingError>,
+        device: &Device,
+    ) -> Result<Self, EmbeddingError> {
+        let Some(modules_path) = fetch("modules.json")? else {
+            return Ok(Self::default());
+        };
+        let modules: Vec<ModuleEntry> = read_json(&modules_path)?;
+
+        let require = |name: String| {
+            fetch(&name)?.ok_or_else(|| {
+                EmbeddingError::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("'{}' is listed in modules.json but was not found", name),
+                ))
+            })
+        };
+
+        let mut pipeline = Self {
+            pooling: Vec::new(),
+            dense: Vec::new(),
+            normalize: false,
+        };
+        for module in modules {
+            match module.module_type.as_str() {
+                POOLING_MODULE => {
+                    let config: PoolingConfig =
+                        read_json(&require(format!("{}/config.json", module.path))?)?;
+                    pipeline.pooling = config.modes();
+                }
+                DENSE_MODULE => {
+                    let config: DenseConfig =
+                        read_json(&require(format!("{}/config.json", module.path))?)?;
+                    let weights = require(format!("{}/model.safetensors", module.path))?;
+                    pipeline.dense.push(Dense::load(config, weights, device)?);
+                }
+                NORMALIZE_MODULE => pipeline.normalize = true,
+                // the Transformer module is the model itself
+                _ => {}
+            }
+        }
+        if pipeline.pooling.is_empty() {
+            pipeline.pooling.push(Pooling::Mean);
+        }
+        Ok(pipeline)
+    }
+
+    /// Whether vectors come out L2-normalized under `options`.
+    pub fn normalizes(&self, options: &EmbedOptions) -> bool {
+        options.normalize.unwrap_or(self.normalize)
+    }
+
+    /// Turns `(batch, seq_len, hidden)` token states into `(batch, dim)` sentence vectors.
+    /// `options` override the declared pooling and normalization.
+    pub fn apply(
+        &self,
+        embeddings: &Tensor,
+        attention_mask: &Tensor,
+        options: &EmbedOptions,
+    ) -> Result<Tensor, EmbeddingError> {
+        let pooling = match options.pooling {
+            Some(pooling) => vec![pooling],
+            None => self.pooling.clone(),
+        };
+        let pooled = pooling
+            .iter()
+            .map(|pooling| pooling.apply(embeddings, attention_mask))
+            .collect::<Result<Vec<_>, EmbeddingError>>()?;
+        self.project(&Tensor::cat(&pooled, 1)?, options)
+    }
+
+    /// Runs already pooled `(batch, hidden)` vectors through the Dense and
+    /// normalization steps.
+    pub fn project(
+        &self,
+        pooled: &Tensor,
+        options: &EmbedOptions,
+    ) -> Result<Tensor, EmbeddingError> {
+        let mut xs = pooled.clone();
+        for dense in &self.dense {
+            xs = dense.forward(&xs)?;
+        }
+        if self.normalizes(options) {
+            xs = normalize_l2(&xs)?;
+        }
+        Ok(xs)
+    }
+}
+
+/// Length of the vectors the model in the folder `root` puts out: the last
+/// Dense module's `out_features`, else `hidden_size` once per pooling mode.
+/// `None` when `modules.json` or a module config can't be read.
+pub fn local_output_dim(root: &Path, hidden_size: usize) -> Option<usize> {
+    let modules_path = root.join("modules.json");
+    if !modules_path.exists() {
+        return Some(hidden_size);
+    }
+    let modules: Vec<ModuleEntry> = read_json(&modules_path).ok()?;
+    let mut dim = hidden_size;
+    for module in modules {
+        let config_path = root.join(&module.path).join("config.json");
+        match module.module_type.as_str() {
+            POOLING_MODULE => {
+                let config: PoolingConfig = read_json(&config_path).ok()?;
+                dim = hidden_size * config.modes().len().max(1);
+            }
+            DENSE_MODULE => {
+                let config: DenseConfig = read_json(&config_path).ok()?;
+                dim = config.out_features;
+            }
+            _ => {}
+        }
+    }
+    Some(dim)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, EmbeddingError> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
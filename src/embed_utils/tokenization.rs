@@ -0,0 +1,354 @@
+use super::EmbeddingError;
+use candle_core::{Device, Tensor};
+use std::str::FromStr;
+use tokenizers::{Encoding, PostProcessor, Tokenizer, TruncationDirection};
+
+/// Which part of an input longer than the token limit is kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Truncation {
+    /// Keep the beginning.
+    Right,
+    /// Keep the end.
+    Left,
+    /// Keep the first and the last half of the budget.
+    HeadTail,
+}
+
+impl FromStr for Truncation {
+    type Err = EmbeddingError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "right" => Ok(Self::Right),
+            "left" => Ok(Self::Left),
+            "head_tail" => Ok(Self::HeadTail),
+            _ => Err(EmbeddingError::InvalidArgument(format!(
+                "Unknown truncation '{}', expected one of: right, left, head_tail",
+                name
+            ))),
+        }
+    }
+}
+
+/// `(start, end)` character span of a token, `None` for a special token.
+pub type TokenSpan = Option<(usize, usize)>;
+
+/// A text's token ids and token strings, special tokens included.
+pub type TokenPieces = (Vec<u32>, Vec<String>);
+
+/// One input ready for the model: token ids including special tokens.
+#[derive(Clone, Debug)]
+pub struct TokenizedText {
+    pub ids: Vec<u32>,
+    pub type_ids: Vec<u32>,
+    /// Whether tokens were dropped to fit the limit.
+    pub truncated: bool,
+}
+
+/// A tokenizer whose built-in padding and truncation are switched off, so both
+/// are decided per call and the tokenizer itself is never mutated.
+pub struct TextTokenizer {
+    tokenizer: Tokenizer,
+    pad_id: u32,
+    /// Longest input (special tokens included) the model's position embeddings allow.
+    max_positions: usize,
+    /// Limit used when none is requested: the tokenizer's own `max_length` if
+    /// `tokenizer.json` declares one, the model's position limit otherwise.
+    default_max_tokens: usize,
+}
+
+impl TextTokenizer {
+    pub fn new(mut tokenizer: Tokenizer, max_positions: usize) -> Result<Self, EmbeddingError> {
+        let pad_id = tokenizer.get_padding().map_or(0, |padding| padding.pad_id);
+        let default_max_tokens = tokenizer
+            .get_truncation()
+            .map_or(max_positions, |truncation| {
+                truncation.max_length.min(max_positions)
+            });
+        tokenizer.with_padding(None);
+        tokenizer.with_truncation(None)?;
+        Ok(Self {
+            tokenizer,
+            pad_id,
+            max_positions,
+            default_max_tokens,
+        })
+    }
+
+    /// Effective token limit, never above what the model can take.
+    pub fn max_tokens(&self, requested: Option<usize>) -> usize {
+        requested.map_or(self.default_max_tokens, |max_tokens| {
+            max_tokens.min(self.max_positions)
+        })
+    }
+
+    /// Special tokens the post-processor wraps a single text in, e.g. `[CLS]` and `[SEP]`.
+    fn added_tokens(&self) -> usize {
+        self.tokenizer
+            .get_post_processor()
+            .map_or(0, |post_processor| post_processor.added_tokens(false))
+    }
+
+    /// Tokenizes `texts`, truncating each one to `max_tokens` including special tokens.
+    pub fn tokenize(
+        &self,
+        texts: Vec<String>,
+        truncation: Truncation,
+        max_tokens: usize,
+    ) -> Result<Vec<TokenizedText>, EmbeddingError> {
+        let added_tokens = self.added_tokens();
+        let budget = max_tokens.saturating_sub(added_tokens);
+
+        self.tokenizer
+            .encode_batch(texts, false)?
+            .into_iter()
+            .map(|encoding| {
+                let truncated = encoding.len() > budget;
+                let encoding = if truncated {
+                    truncate(encoding, budget, truncation)
+                } else {
+                    encoding
+                };
+                let encoding = self.tokenizer.post_process(encoding, None, true)?;
+                Ok(TokenizedText {
+                    ids: encoding.get_ids().to_vec(),
+                    type_ids: encoding.get_type_ids().to_vec(),
+                    truncated,
+                })
+            })
+            .collect()
+    }
+
+    /// Tokens `max_tokens` leaves for text once special tokens are added.
+    pub fn text_budget(&self, max_tokens: Option<usize>) -> usize {
+        self.max_tokens(max_tokens)
+            .saturating_sub(self.added_tokens())
+    }
+
+    /// Full tokenization of each text, special tokens included and nothing
+    /// truncated, as `(ids, token strings)`.
+    pub fn encode_full(&self, texts: Vec<String>) -> Result<Vec<TokenPieces>, EmbeddingError> {
+        Ok(self
+            .tokenizer
+            .encode_batch(texts, true)?
+            .into_iter()
+            .map(|encoding| (encoding.get_ids().to_vec(), encoding.get_tokens().to_vec()))
+            .collect())
+    }
+
+    /// Text of `ids`, leaving out special tokens.
+    pub fn decode(&self, ids: &[u32]) -> Result<String, EmbeddingError> {
+        Ok(self.tokenizer.decode(ids, true)?)
+    }
+
+    /// `(start, end)` character span of every token of `text`, without special
+    /// tokens and without truncation.
+    pub fn token_offsets(&self, text: &str) -> Result<Vec<(usize, usize)>, EmbeddingError> {
+        Ok(self
+            .tokenizer
+            .encode_char_offsets(text, false)?
+            .get_offsets()
+            .to_vec())
+    }
+
+    /// Tokenizes one text like `tokenize` with `Truncation::Right`, also returning
+    /// each token's `(start, end)` character span; special tokens get `None`.
+    pub fn tokenize_with_offsets(
+        &self,
+        text: &str,
+        max_tokens: usize,
+    ) -> Result<(TokenizedText, Vec<TokenSpan>), EmbeddingError> {
+        let budget = max_tokens.saturating_sub(self.added_tokens());
+        let encoding = self.tokenizer.encode_char_offsets(text, false)?;
+        let truncated = encoding.len() > budget;
+        let encoding = if truncated {
+            truncate(encoding, budget, Truncation::Right)
+        } else {
+            encoding
+        };
+        let encoding = self.tokenizer.post_process(encoding, None, true)?;
+        let offsets = encoding
+            .get_offsets()
+            .iter()
+            .zip(encoding.get_special_tokens_mask())
+            .map(|(&offsets, &special)| (special == 0).then_some(offsets))
+            .collect();
+        Ok((
+            TokenizedText {
+                ids: encoding.get_ids().to_vec(),
+                type_ids: encoding.get_type_ids().to_vec(),
+                truncated,
+            },
+            offsets,
+        ))
+    }
+
+    /// Tokenizes `(first, second)` pairs as one input each, e.g. a query and a
+    /// document for a cross-encoder: `[CLS] first [SEP] second [SEP]` with
+    /// segment id 0 for the first text and 1 for the second. Over `max_tokens`,
+    /// tokens are dropped from the end of the longer text first.
+    pub fn tokenize_pairs(
+        &self,
+        pairs: Vec<(String, String)>,
+        max_tokens: usize,
+    ) -> Result<Vec<TokenizedText>, EmbeddingError> {
+        let added_tokens = self
+            .tokenizer
+            .get_post_processor()
+            .map_or(0, |post_processor| post_processor.added_tokens(true));
+        let budget = max_tokens.saturating_sub(added_tokens);
+        let (firsts, seconds): (Vec<_>, Vec<_>) = pairs.into_iter().unzip();
+        let firsts = self.tokenizer.encode_batch(firsts, false)?;
+        let seconds = self.tokenizer.encode_batch(seconds, false)?;
+
+        firsts
+            .into_iter()
+            .zip(seconds)
+            .map(|(first, second)| {
+                let (mut first_len, mut second_len) = (first.len(), second.len());
+                let truncated = first_len + second_len > budget;
+                while first_len + second_len > budget {
+                    if first_len > second_len {
+                        first_len -= 1;
+                    } else {
+                        second_len -= 1;
+                    }
+                }
+                let first = truncate(first, first_len, Truncation::Right);
+                let second = truncate(second, second_len, Truncation::Right);
+                let encoding = self.tokenizer.post_process(first, Some(second), true)?;
+                Ok(TokenizedText {
+                    ids: encoding.get_ids().to_vec(),
+                    type_ids: encoding.get_type_ids().to_vec(),
+                    truncated,
+                })
+            })
+            .collect()
+    }
+
+    /// Tokenizes each text in full and cuts it into windows of at most `window`
+    /// tokens (special tokens included) whose starts are `stride` tokens apart.
+    /// A text always yields at least one window.
+    pub fn windows(
+        &self,
+        texts: Vec<String>,
+        window: usize,
+        stride: usize,
+    ) -> Result<Vec<Vec<TokenizedText>>, EmbeddingError> {
+        let window = window.min(self.max_positions);
+        let added_tokens = self.added_tokens();
+        let budget = window.saturating_sub(added_tokens);
+        if budget == 0 || stride == 0 {
+            return Err(EmbeddingError::InvalidArgument(format!(
+                "window must leave room for text after {} special tokens and stride must be positive, got window {} and stride {}",
+                added_tokens, window, stride
+            )));
+        }
+        // a stride longer than the window would skip tokens
+        let stride = stride.min(budget);
+
+        self.tokenizer
+            .encode_batch(texts, false)?
+            .into_iter()
+            .map(|encoding| {
+                let len = encoding.len();
+                let mut windows = Vec::new();
+                let mut start = 0;
+                loop {
+                    let mut piece = encoding.clone();
+                    piece.truncate(len - start, 0, TruncationDirection::Left);
+                    piece.take_overflowing();
+                    let truncated = start > 0 || piece.len() > budget;
+                    piece.truncate(budget, 0, TruncationDirection::Right);
+                    piece.take_overflowing();
+                    let piece = self.tokenizer.post_process(piece, None, true)?;
+                    windows.push(TokenizedText {
+                        ids: piece.get_ids().to_vec(),
+                        type_ids: piece.get_type_ids().to_vec(),
+                        truncated,
+                    });
+                    if start + budget >= len {
+                        break;
+                    }
+                    start += stride;
+                }
+                Ok(windows)
+            })
+            .collect()
+    }
+
+    /// Right-pads `batch` to its longest member, returning
+    /// `(input_ids, token_type_ids, attention_mask)` as `(batch, seq_len)` tensors.
+    pub fn batch_tensors(
+        &self,
+        batch: &[&TokenizedText],
+        device: &Device,
+    ) -> Result<(Tensor, Tensor, Tensor), EmbeddingError> {
+        let seq_len = batch.iter().map(|text| text.ids.len()).max().unwrap_or(0);
+        let mut ids = Vec::with_capacity(batch.len() * seq_len);
+        let mut type_ids = Vec::with_capacity(batch.len() * seq_len);
+        let mut attention_mask = Vec::with_capacity(batch.len() * seq_len);
+
+        for text in batch {
+            let row_end = ids.len() + seq_len;
+            ids.extend_from_slice(&text.ids);
+            ids.resize(row_end, self.pad_id);
+            type_ids.extend_from_slice(&text.type_ids);
+            type_ids.resize(row_end, 0);
+            attention_mask.resize(attention_mask.len() + text.ids.len(), 1u32);
+            attention_mask.resize(row_end, 0);
+        }
+
+        let shape = (batch.len(), seq_len);
+        Ok((
+            Tensor::from_vec(ids, shape, device)?,
+            Tensor::from_vec(type_ids, shape, device)?,
+            Tensor::from_vec(attention_mask, shape, device)?,
+        ))
+    }
+}
+
+/// Batches of input positions for inputs of `lengths` tokens: positions are
+/// sorted by length, so each batch is padded little, and a batch takes as many
+/// inputs as fit `max_batch_tokens` once padded to its longest (at least one).
+pub fn length_batches(lengths: &[usize], max_batch_tokens: usize) -> Vec<Vec<usize>> {
+    let mut order = (0..lengths.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&position| lengths[position]);
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    for position in order {
+        match batches.last_mut() {
+            // sorted, so the newcomer is the longest of its batch
+            Some(batch) if (batch.len() + 1) * lengths[position] <= max_batch_tokens => {
+                batch.push(position)
+            }
+            _ => batches.push(vec![position]),
+        }
+    }
+    batches
+}
+
+/// Cuts `encoding` (without special tokens) down to `budget` tokens.
+fn truncate(mut encoding: Encoding, budget: usize, truncation: Truncation) -> Encoding {
+    let mut encoding = match truncation {
+        Truncation::Right => {
+            encoding.truncate(budget, 0, TruncationDirection::Right);
+            encoding
+        }
+        Truncation::Left => {
+            encoding.truncate(budget, 0, TruncationDirection::Left);
+            encoding
+        }
+        Truncation::HeadTail => {
+            let head_len = budget / 2;
+            let mut head = encoding.clone();
+            head.truncate(head_len, 0, TruncationDirection::Right);
+            head.take_overflowing();
+            encoding.truncate(budget - head_len, 0, TruncationDirection::Left);
+            encoding.take_overflowing();
+            Encoding::merge([head, encoding], false)
+        }
+    };
+    // the dropped tokens are not embedded, don't carry them along
+    encoding.take_overflowing();
+    encoding
+}
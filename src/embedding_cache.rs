@@ -0,0 +1,236 @@
+use crate::embed_utils::EmbeddingError;
+use crate::model_registry::lock_or_error;
+use crate::settings;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+mod disk;
+use disk::{key_hash, DiskCache};
+
+/// A model fingerprint and a normalized text.
+type CacheKey = (String, String);
+
+struct CacheEntry {
+    embedding: Vec<f32>,
+    /// Tick of the last lookup or insert, the entry's place in `recency`.
+    last_used: u64,
+}
+
+/// Counters reported by `quackformers_cache_stats()`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Embeddings in the `cache_path` file, `None` when there is none.
+    pub disk_entries: Option<usize>,
+}
+
+/// Least-recently-used map from (model fingerprint, text) to embeddings. Hits
+/// and misses count lookups in it and the `cache_path` file together.
+#[derive(Default)]
+pub struct EmbeddingCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Keys by last use, oldest first.
+    recency: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl EmbeddingCache {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<Vec<f32>> {
+        let tick = self.next_tick();
+        let entry = self.entries.get_mut(key)?;
+        let key = self
+            .recency
+            .remove(&entry.last_used)
+            .unwrap_or_else(|| key.clone());
+        entry.last_used = tick;
+        self.recency.insert(tick, key);
+        Some(entry.embedding.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, embedding: Vec<f32>, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        let tick = self.next_tick();
+        let previous = self.entries.insert(
+            key.clone(),
+            CacheEntry {
+                embedding,
+                last_used: tick,
+            },
+        );
+        if let Some(previous) = previous {
+            self.recency.remove(&previous.last_used);
+        }
+        self.recency.insert(tick, key);
+        self.evict(capacity);
+    }
+
+    /// Drops the least recently used entries until at most `capacity` remain.
+    fn evict(&mut self, capacity: usize) {
+        while self.entries.len() > capacity {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&key);
+        }
+    }
+
+    fn record(&mut self, hits: usize, misses: usize) {
+        self.hits += hits as u64;
+        self.misses += misses as u64;
+    }
+
+    /// Empties the cache and resets its counters, returning how many entries it held.
+    pub fn clear(&mut self) -> usize {
+        let entries = self.entries.len();
+        *self = Self::default();
+        entries
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            capacity: settings::cache_size(),
+            hits: self.hits,
+            misses: self.misses,
+            disk_entries: None,
+        }
+    }
+}
+
+pub static EMBEDDING_CACHE: Lazy<Mutex<EmbeddingCache>> =
+    Lazy::new(|| Mutex::new(EmbeddingCache::default()));
+
+/// The file set with `cache_path`, if any.
+static DISK_CACHE: Lazy<Mutex<Option<DiskCache>>> = Lazy::new(|| Mutex::new(None));
+
+fn lock_cache() -> Result<MutexGuard<'static, EmbeddingCache>, EmbeddingError> {
+    lock_or_error(Lazy::force(&EMBEDDING_CACHE), "embedding cache")
+}
+
+fn lock_disk_cache() -> Result<MutexGuard<'static, Option<DiskCache>>, EmbeddingError> {
+    lock_or_error(Lazy::force(&DISK_CACHE), "embedding cache file")
+}
+
+/// Keeps embeddings in the file at `path` from now on, reading back what it
+/// already holds; an empty path stops using a file.
+pub fn set_cache_path(path: &str) -> Result<(), EmbeddingError> {
+    let disk_cache = if path.is_empty() {
+        None
+    } else {
+        Some(DiskCache::open(Path::new(path))?)
+    };
+    *lock_disk_cache()? = disk_cache;
+    Ok(())
+}
+
+/// Path of the cache file, empty when there is none.
+pub fn cache_path() -> Result<String, EmbeddingError> {
+    Ok(lock_disk_cache()?
+        .as_ref()
+        .map_or_else(String::new, |disk_cache| {
+            disk_cache.path().display().to_string()
+        }))
+}
+
+/// Texts differing only in surrounding whitespace embed alike, so they share an entry.
+fn normalize_text(text: &str) -> String {
+    text.trim().to_string()
+}
+
+/// Embeds `texts` for the model identified by `fingerprint`, taking what it can
+/// from the in-memory cache, then from the cache file, and running `embed` on
+/// the rest, whose vectors are then cached in both. No cache is locked while
+/// `embed` runs.
+pub fn embed_cached(
+    fingerprint: &str,
+    texts: Vec<String>,
+    embed: impl FnOnce(Vec<String>) -> Result<Vec<Vec<f32>>, EmbeddingError>,
+) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+    let capacity = settings::cache_size();
+    let mut disk_cache = lock_disk_cache()?;
+    if capacity == 0 && disk_cache.is_none() {
+        return embed(texts);
+    }
+    let keys = texts
+        .iter()
+        .map(|text| (fingerprint.to_string(), normalize_text(text)))
+        .collect::<Vec<_>>();
+
+    let mut embeddings = {
+        let mut cache = lock_cache()?;
+        keys.iter().map(|key| cache.get(key)).collect::<Vec<_>>()
+    };
+    if let Some(disk_cache) = disk_cache.as_mut() {
+        let mut from_disk = Vec::new();
+        for (row, (fingerprint, text)) in keys.iter().enumerate() {
+            if embeddings[row].is_none() {
+                embeddings[row] = disk_cache.get(key_hash(fingerprint, text))?;
+                from_disk.extend(embeddings[row].is_some().then_some(row));
+            }
+        }
+        let mut cache = lock_cache()?;
+        for row in from_disk {
+            if let Some(embedding) = &embeddings[row] {
+                cache.insert(keys[row].clone(), embedding.clone(), capacity);
+            }
+        }
+    }
+    drop(disk_cache);
+
+    let missing = (0..texts.len())
+        .filter(|&row| embeddings[row].is_none())
+        .collect::<Vec<_>>();
+    lock_cache()?.record(texts.len() - missing.len(), missing.len());
+    if missing.is_empty() {
+        return Ok(embeddings.into_iter().flatten().collect());
+    }
+
+    let computed = embed(missing.iter().map(|&row| texts[row].clone()).collect())?;
+    for (&row, embedding) in missing.iter().zip(computed) {
+        embeddings[row] = Some(embedding);
+    }
+    {
+        let mut cache = lock_cache()?;
+        for &row in &missing {
+            if let Some(embedding) = &embeddings[row] {
+                cache.insert(keys[row].clone(), embedding.clone(), capacity);
+            }
+        }
+    }
+    if let Some(disk_cache) = lock_disk_cache()?.as_mut() {
+        disk_cache.insert_all(missing.iter().filter_map(|&row| {
+            let (fingerprint, text) = &keys[row];
+            Some((key_hash(fingerprint, text), embeddings[row].as_deref()?))
+        }))?;
+    }
+    Ok(embeddings.into_iter().flatten().collect())
+}
+
+pub fn cache_stats() -> Result<CacheStats, EmbeddingError> {
+    let mut stats = lock_cache()?.stats();
+    stats.disk_entries = lock_disk_cache()?.as_ref().map(DiskCache::entries);
+    Ok(stats)
+}
+
+pub fn clear_cache() -> Result<usize, EmbeddingError> {
+    Ok(lock_cache()?.clear())
+}
+
+/// Shrinks the cache to a new `cache_size`.
+pub fn resize_cache(capacity: usize) -> Result<(), EmbeddingError> {
+    lock_cache()?.evict(capacity);
+    Ok(())
+}
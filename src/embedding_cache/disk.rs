@@ -0,0 +1,150 @@
+use crate::embed_utils::EmbeddingError;
+use crate::hash::fnv1a;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// First bytes of a cache file, bumped whenever the record layout changes.
+const MAGIC: &[u8; 8] = b"QFCACHE1";
+
+/// Hash of a cache key, FNV-1a over 128 bits.
+pub type KeyHash = u128;
+
+/// Hashes a model fingerprint and a normalized text into the key of a record.
+pub fn key_hash(fingerprint: &str, text: &str) -> KeyHash {
+    // 0xff never occurs in UTF-8, so the two parts can't run into each other
+    fnv1a(
+        fingerprint
+            .as_bytes()
+            .iter()
+            .chain(&[0xff])
+            .chain(text.as_bytes()),
+    )
+}
+
+/// An append-only file of embeddings: after `MAGIC`, records of a 16-byte key
+/// hash, a `u32` length and that many `f32`s, all little-endian. Only the
+/// offsets live in memory; vectors are read back on demand.
+pub struct DiskCache {
+    path: PathBuf,
+    file: File,
+    /// Offset of each record's length field.
+    index: HashMap<KeyHash, u64>,
+}
+
+impl DiskCache {
+    /// Opens the cache at `path`, creating it if needed. A record cut short by
+    /// a crash is dropped.
+    pub fn open(path: &Path) -> Result<Self, EmbeddingError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let file_len = file.metadata()?.len();
+        if file_len == 0 {
+            file.write_all(MAGIC)?;
+        }
+        let file_len = file_len.max(MAGIC.len() as u64);
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut reader = BufReader::new(&mut file);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(EmbeddingError::InvalidArgument(format!(
+                "{} is not a quackformers cache file",
+                path.display()
+            )));
+        }
+
+        let mut index = HashMap::new();
+        let mut offset = MAGIC.len() as u64;
+        let mut header = [0; 20];
+        loop {
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error.into()),
+            }
+            let hash = u128::from_le_bytes(header[..16].try_into().unwrap_or_default());
+            let len = u32::from_le_bytes(header[16..].try_into().unwrap_or_default()) as u64;
+            let record_end = offset + 20 + 4 * len;
+            if record_end > file_len {
+                break;
+            }
+            reader.seek_relative(4 * len as i64)?;
+            index.insert(hash, offset + 16);
+            offset = record_end;
+        }
+        drop(reader);
+        file.set_len(offset)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            index,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of embeddings stored.
+    pub fn entries(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn get(&mut self, hash: KeyHash) -> Result<Option<Vec<f32>>, EmbeddingError> {
+        let Some(&offset) = self.index.get(&hash) else {
+            return Ok(None);
+        };
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut len = [0; 4];
+        self.file.read_exact(&mut len)?;
+        let mut bytes = vec![0; 4 * u32::from_le_bytes(len) as usize];
+        self.file.read_exact(&mut bytes)?;
+        Ok(Some(
+            bytes
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                .collect(),
+        ))
+    }
+
+    /// Appends records, skipping keys already stored. They are only indexed
+    /// once written, and a failed write is cut back off the file.
+    pub fn insert_all<'a>(
+        &mut self,
+        records: impl Iterator<Item = (KeyHash, &'a [f32])>,
+    ) -> Result<(), EmbeddingError> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        let mut buffer = Vec::new();
+        let mut written = HashMap::new();
+        for (hash, embedding) in records {
+            if self.index.contains_key(&hash) || written.contains_key(&hash) {
+                continue;
+            }
+            written.insert(hash, offset + buffer.len() as u64 + 16);
+            buffer.extend_from_slice(&hash.to_le_bytes());
+            buffer.extend_from_slice(&(embedding.len() as u32).to_le_bytes());
+            for value in embedding {
+                buffer.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        if let Err(error) = self
+            .file
+            .write_all(&buffer)
+            .and_then(|()| self.file.flush())
+        {
+            // drop whatever part made it, so later records still line up
+            let _ = self.file.set_len(offset);
+            return Err(error.into());
+        }
+        self.index.extend(written);
+        Ok(())
+    }
+}
@@ -0,0 +1,14 @@
+//! FNV-1a over 128 bits: stable across runs and platforms, so its values can
+//! be written to disk.
+
+const FNV_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+pub fn fnv1a<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u128 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for &byte in bytes {
+        hash ^= byte as u128;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
@@ -2,10 +2,9 @@ extern crate duckdb;
 extern crate duckdb_loadable_macros;
 extern crate libduckdb_sys;
 
-use candle_core::Device;
 use duckdb::ffi;
 use duckdb::{
-    core::{DataChunkHandle, LogicalTypeHandle, LogicalTypeId},
+    core::{DataChunkHandle, Inserter, LogicalTypeHandle, LogicalTypeId},
     vscalar::{ScalarFunctionSignature, VScalar},
     vtab::arrow::WritableVector,
     Connection, Result,
@@ -14,12 +13,26 @@ use duckdb_loadable_macros::duckdb_entrypoint_c_api;
 use libduckdb_sys::{duckdb_string_t, duckdb_string_t_data, duckdb_string_t_length};
 use std::error::Error;
 use std::slice;
-use once_cell::sync::Lazy;
 mod embed_utils;
-use embed_utils::{Embed, EmbeddingError, ModelType, TextEmbedder};
-use std::sync::Mutex;
-
-const DEVICE: Device = Device::Cpu;
+mod embedding_cache;
+mod hash;
+mod model_registry;
+mod settings;
+mod table_functions;
+mod text_split;
+mod tokenizer_functions;
+use embed_utils::{EmbedOptions, EmbeddingError, Pooling, TextEmbedder, WindowCombine};
+use model_registry::{
+    builtin_model_dim, catch_panic, get_model, DEFAULT_MODEL, JINA_MODEL, RERANK_MODEL,
+};
+use table_functions::{
+    CacheStatsVTab, ChunkTextVTab, EmbedSentencesVTab, LateChunksVTab, RegisterModelVTab,
+    RerankVTab, SplitMarkdownVTab, SplitSentencesVTab,
+};
+use tokenizer_functions::{
+    DetokenizeFunc, IsTruncatedFunc, TokenCountFunc, TokenizeFunc, TokenizePiecesFunc,
+    TruncateToTokensFunc,
+};
 
 fn duckdb_string_to_owned_string(word: &duckdb_string_t) -> String {
     unsafe {
@@ -30,62 +43,166 @@ fn duckdb_string_to_owned_string(word: &duckdb_string_t) -> String {
     }
 }
 
-fn process_strings(input_slice: &[duckdb_string_t]) -> Result<Vec<String>, EmbeddingError> {
-    input_slice
-        .iter()
-        .map(|word| Ok(duckdb_string_to_owned_string(word)))
-        .collect::<Result<Vec<String>, EmbeddingError>>()
-}
-
-/// Load & JIT once on first use:
-static BERT_EMBEDDER: Lazy<Mutex<TextEmbedder>> = Lazy::new(|| {
-    let mut embedder = ModelType::Bert(DEVICE)
-        .build_text_embedder()
-        .expect("failed to load BERT embedder");
-    // Warm up: do one dummy forward to JIT kernels
-    let dummy = ["hello world".to_string()].to_vec();
-    let _ = embedder.embed(dummy, /*batch_size=*/1);
-    Mutex::new(embedder)
-});
-
-static JINA_EMBEDDER: Lazy<Mutex<TextEmbedder>> = Lazy::new(|| {
-    let mut embedder = ModelType::Jina(DEVICE)
-        .build_text_embedder()
-        .expect("failed to load Jina embedder");
-    let dummy = ["hello world".to_string()].to_vec();
-    let _ = embedder.embed(dummy, /*batch_size=*/1);
-    Mutex::new(embedder)
-});
-
-
-unsafe fn generic_embed_invoke(
-    input: &mut DataChunkHandle,
-    output: &mut dyn WritableVector,
-    use_jina: bool,
-) -> Result<(), Box<dyn Error>> {
-    let input_vec = input.flat_vector(0);
+/// Reads a VARCHAR column, mapping NULL rows to `None`.
+unsafe fn read_nullable_strings(input: &DataChunkHandle, column: usize) -> Vec<Option<String>> {
+    let input_vec = input.flat_vector(column);
     // slice of strings
     let input_slice = input_vec.as_slice_with_len::<duckdb_string_t>(input.len());
+    input_slice
+        .iter()
+        .enumerate()
+        .map(|(row, word)| {
+            (!input_vec.row_is_null(row as u64)).then(|| duckdb_string_to_owned_string(word))
+        })
+        .collect()
+}
 
-    // let output_flat_vector = output.flat_vector();
-    let mut output_list_vector = output.list_vector();
+/// Reads a BOOLEAN column, mapping NULL rows to `None`.
+unsafe fn read_nullable_bools(input: &DataChunkHandle, column: usize) -> Vec<Option<bool>> {
+    let input_vec = input.flat_vector(column);
+    let input_slice = input_vec.as_slice_with_len::<bool>(input.len());
+    input_slice
+        .iter()
+        .enumerate()
+        .map(|(row, value)| (!input_vec.row_is_null(row as u64)).then_some(*value))
+        .collect()
+}
 
-    // Bert embed
-    let vect_phrases = process_strings(input_slice)?;
-    // choose the already-loaded embedder
-    let mut guard = if use_jina {
-        JINA_EMBEDDER.lock().unwrap()
+/// Reads an INTEGER column, mapping NULL rows to `None`.
+unsafe fn read_nullable_ints(input: &DataChunkHandle, column: usize) -> Vec<Option<i32>> {
+    let input_vec = input.flat_vector(column);
+    let input_slice = input_vec.as_slice_with_len::<i32>(input.len());
+    input_slice
+        .iter()
+        .enumerate()
+        .map(|(row, value)| (!input_vec.row_is_null(row as u64)).then_some(*value))
+        .collect()
+}
+
+/// Row indices per distinct key, in first-seen order; `None` keys are dropped.
+fn group_rows<K: PartialEq>(keys: Vec<Option<K>>) -> Vec<(K, Vec<usize>)> {
+    let mut groups: Vec<(K, Vec<usize>)> = Vec::new();
+    for (row, key) in keys.into_iter().enumerate() {
+        let Some(key) = key else {
+            continue;
+        };
+        match groups.iter_mut().find(|(group_key, _)| *group_key == key) {
+            Some((_, rows)) => rows.push(row),
+            None => groups.push((key, vec![row])),
+        }
+    }
+    groups
+}
+
+/// Model name per row from column `column`, or `DEFAULT_MODEL` for every row
+/// when the call has no such column.
+unsafe fn read_model_names(input: &DataChunkHandle, column: usize) -> Vec<Option<String>> {
+    if input.num_columns() > column {
+        read_nullable_strings(input, column)
     } else {
-        BERT_EMBEDDER.lock().unwrap()
+        vec![Some(DEFAULT_MODEL.to_string()); input.len()]
+    }
+}
+
+/// Runs `f` once per model over the rows using it, with the model's embedder,
+/// its registered options and the row indices; `f` returns one value per row.
+/// Rows whose model name is `None` are skipped and come back as `None`.
+/// `action` names what `f` does in the error reported if it panics.
+fn map_rows_per_model<T>(
+    action: &str,
+    model_names: Vec<Option<String>>,
+    mut f: impl FnMut(&TextEmbedder, &EmbedOptions, &[usize]) -> Result<Vec<T>, EmbeddingError>,
+) -> Result<Vec<Option<T>>, Box<dyn Error>> {
+    let mut results = model_names.iter().map(|_| None).collect::<Vec<_>>();
+    for (model_name, rows) in group_rows(model_names) {
+        let model = get_model(&model_name)?;
+        let embedder = model.embedder()?;
+        let values = catch_panic(action, || f(embedder, model.options(), &rows))?;
+        for (row, value) in rows.into_iter().zip(values) {
+            results[row] = Some(value);
+        }
+    }
+    Ok(results)
+}
+
+/// Embeds the text in column 0. The optional columns after it are, in order,
+/// the model name (`default_model` when absent), a pooling override and a
+/// normalization override, as in `embed(text, model, pooling, normalize)`.
+/// Rows are grouped per model and options so each group runs once per chunk.
+/// Rows with any NULL argument are skipped before tokenization and come back
+/// as `None`.
+unsafe fn embed_rows(
+    input: &mut DataChunkHandle,
+    default_model: &str,
+) -> Result<Vec<Option<Vec<f32>>>, Box<dyn Error>> {
+    let num_columns = input.num_columns();
+    let vect_phrases = read_nullable_strings(input, 0);
+
+    let model_names = if num_columns > 1 {
+        read_nullable_strings(input, 1)
+    } else {
+        vec![Some(default_model.to_string()); vect_phrases.len()]
     };
-    // now we have a `&mut TextEmbedder`
-    let embedded_phrases = guard.embed(vect_phrases, /*batch_size=*/32)?;
-    // …rest of your write-out logic…
-    let total_len: usize = embedded_phrases.iter().map(|v| v.len()).sum();
+    let pooling_names = (num_columns > 2).then(|| read_nullable_strings(input, 2));
+    let normalize_flags = (num_columns > 3).then(|| read_nullable_bools(input, 3));
+
+    let mut keys = Vec::with_capacity(vect_phrases.len());
+    for (row, phrase) in vect_phrases.iter().enumerate() {
+        let (Some(_), Some(model_name)) = (phrase, &model_names[row]) else {
+            keys.push(None);
+            continue;
+        };
+        let mut options = EmbedOptions::default();
+        if let Some(pooling_names) = &pooling_names {
+            let Some(pooling_name) = &pooling_names[row] else {
+                keys.push(None);
+                continue;
+            };
+            options.pooling = Some(pooling_name.parse::<Pooling>()?);
+        }
+        if let Some(normalize_flags) = &normalize_flags {
+            let Some(normalize) = normalize_flags[row] else {
+                keys.push(None);
+                continue;
+            };
+            options.normalize = Some(normalize);
+        }
+        keys.push(Some((model_name.clone(), options)));
+    }
+
+    let mut embedded_phrases: Vec<Option<Vec<f32>>> = vec![None; vect_phrases.len()];
+    for ((model_name, options), rows) in group_rows(keys) {
+        let model = get_model(&model_name)?;
+        // call arguments win over the options the model was registered with
+        let options = options.or(model.options());
+        let phrases = rows
+            .iter()
+            .filter_map(|&row| vect_phrases[row].clone())
+            .collect();
+        let embeddings = model.embed(phrases, &options)?;
+        for (row, embedding) in rows.into_iter().zip(embeddings) {
+            embedded_phrases[row] = Some(embedding);
+        }
+    }
+    Ok(embedded_phrases)
+}
+
+/// Writes one list per row, e.g. `FLOAT[]` embeddings, NULL where there is none.
+fn write_list_output<T: Copy>(
+    output: &mut dyn WritableVector,
+    embedded_phrases: &[Option<Vec<T>>],
+) -> Result<(), Box<dyn Error>> {
+    let mut output_list_vector = output.list_vector();
+    let total_len: usize = embedded_phrases.iter().flatten().map(|v| v.len()).sum();
     let mut child_vector = output_list_vector.child(total_len);
 
     let mut offset = 0;
     for (i, embedded_phrase) in embedded_phrases.iter().enumerate() {
+        let Some(embedded_phrase) = embedded_phrase else {
+            output_list_vector.set_entry(i, offset, 0);
+            output_list_vector.set_null(i);
+            continue;
+        };
         child_vector.as_mut_slice_with_len(offset + embedded_phrase.len())
             [offset..offset + embedded_phrase.len()]
             .copy_from_slice(embedded_phrase);
@@ -94,11 +211,63 @@ unsafe fn generic_embed_invoke(
 
         offset += embedded_phrase.len();
     }
-    output_list_vector.set_len(embedded_phrases.len());
+    output_list_vector.set_len(total_len);
 
     Ok(())
 }
 
+/// Writes one `FLOAT[dim]` array per row, rejecting vectors of any other length
+/// so a mismatched model can never mix dimensions in a column.
+fn write_array_output(
+    output: &mut dyn WritableVector,
+    embedded_phrases: &[Option<Vec<f32>>],
+    dim: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut output_array_vector = output.array_vector();
+    let mut child_vector = output_array_vector.child(embedded_phrases.len() * dim);
+    let child_slice = child_vector.as_mut_slice_with_len::<f32>(embedded_phrases.len() * dim);
+
+    for (i, embedded_phrase) in embedded_phrases.iter().enumerate() {
+        let Some(embedded_phrase) = embedded_phrase else {
+            output_array_vector.set_null(i);
+            continue;
+        };
+        if embedded_phrase.len() != dim {
+            return Err(EmbeddingError::ModelTypeError(format!(
+                "Model produced {} dimensions, expected FLOAT[{}]",
+                embedded_phrase.len(),
+                dim
+            ))
+            .into());
+        }
+        child_slice[i * dim..(i + 1) * dim].copy_from_slice(embedded_phrase);
+    }
+
+    Ok(())
+}
+
+/// Return type of a single-model embedding function: the model's exact
+/// `FLOAT[dim]` when it is known up front, a plain `FLOAT[]` list otherwise.
+fn model_output_type(model_name: &str) -> LogicalTypeHandle {
+    match builtin_model_dim(model_name) {
+        Some(dim) => LogicalTypeHandle::array(&LogicalTypeId::Float.into(), dim as u64),
+        None => LogicalTypeHandle::list(&LogicalTypeId::Float.into()),
+    }
+}
+
+/// Embeds with a single fixed model and writes `FLOAT[dim]` output.
+unsafe fn fixed_model_embed_invoke(
+    input: &mut DataChunkHandle,
+    output: &mut dyn WritableVector,
+    model_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let embedded_phrases = embed_rows(input, model_name)?;
+    match builtin_model_dim(model_name) {
+        Some(dim) => write_array_output(output, &embedded_phrases, dim),
+        None => write_list_output(output, &embedded_phrases),
+    }
+}
+
 struct EmbedFunc;
 
 impl VScalar for EmbedFunc {
@@ -114,20 +283,337 @@ impl VScalar for EmbedFunc {
         input: &mut DataChunkHandle,
         output: &mut dyn WritableVector,
     ) -> Result<(), Box<dyn Error>> {
-        generic_embed_invoke(input, output, /*use_jina=*/ false)
+        // embed(text) uses the default model and returns its exact FLOAT[dim];
+        // embed(text, model, ...) picks the model per row, so its size is only
+        // known at run time and it returns FLOAT[]
+        if input.num_columns() > 1 {
+            let embedded_phrases = embed_rows(input, DEFAULT_MODEL)?;
+            write_list_output(output, &embedded_phrases)
+        } else {
+            fixed_model_embed_invoke(input, output, DEFAULT_MODEL)
+        }
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        vec![
+            ScalarFunctionSignature::exact(
+                vec![LogicalTypeId::Varchar.into()],
+                model_output_type(DEFAULT_MODEL),
+            ),
+            ScalarFunctionSignature::exact(
+                vec![LogicalTypeId::Varchar.into(), LogicalTypeId::Varchar.into()],
+                LogicalTypeHandle::list(&LogicalTypeId::Float.into()),
+            ),
+            // embed(text, model, pooling)
+            ScalarFunctionSignature::exact(
+                vec![
+                    LogicalTypeId::Varchar.into(),
+                    LogicalTypeId::Varchar.into(),
+                    LogicalTypeId::Varchar.into(),
+                ],
+                LogicalTypeHandle::list(&LogicalTypeId::Float.into()),
+            ),
+            // embed(text, model, pooling, normalize)
+            ScalarFunctionSignature::exact(
+                vec![
+                    LogicalTypeId::Varchar.into(),
+                    LogicalTypeId::Varchar.into(),
+                    LogicalTypeId::Varchar.into(),
+                    LogicalTypeId::Boolean.into(),
+                ],
+                LogicalTypeHandle::list(&LogicalTypeId::Float.into()),
+            ),
+        ]
+    }
+}
+
+struct EmbedJinaFunc;
+
+impl VScalar for EmbedJinaFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        fixed_model_embed_invoke(input, output, JINA_MODEL)
     }
 
     fn signatures() -> Vec<ScalarFunctionSignature> {
         vec![ScalarFunctionSignature::exact(
             vec![LogicalTypeId::Varchar.into()],
-            LogicalTypeHandle::list(&LogicalTypeId::Float.into()),
+            model_output_type(JINA_MODEL),
         )]
     }
 }
 
-struct EmbedJinaFunc;
+const DEFAULT_WINDOW: usize = 512;
+const DEFAULT_STRIDE: usize = 256;
 
-impl VScalar for EmbedJinaFunc {
+/// Reads a window or stride argument, which must be positive.
+fn window_argument(name: &str, value: i32) -> Result<usize, EmbeddingError> {
+    usize::try_from(value)
+        .ok()
+        .filter(|&value| value > 0)
+        .ok_or_else(|| {
+            EmbeddingError::InvalidArgument(format!("{} must be positive, got {}", name, value))
+        })
+}
+
+struct EmbedLongFunc;
+
+impl VScalar for EmbedLongFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        // embed_long(text[, window, stride[, combine]]) with the default model
+        let num_columns = input.num_columns();
+        let texts = read_nullable_strings(input, 0);
+        let windows = (num_columns > 1).then(|| read_nullable_ints(input, 1));
+        let strides = (num_columns > 2).then(|| read_nullable_ints(input, 2));
+        let combines = (num_columns > 3).then(|| read_nullable_strings(input, 3));
+
+        let mut keys = Vec::with_capacity(texts.len());
+        for (row, text) in texts.iter().enumerate() {
+            let window = windows
+                .as_ref()
+                .map_or(Some(DEFAULT_WINDOW as i32), |w| w[row]);
+            let stride = strides
+                .as_ref()
+                .map_or(Some(DEFAULT_STRIDE as i32), |s| s[row]);
+            let combine = combines
+                .as_ref()
+                .map_or(Some("mean".to_string()), |c| c[row].clone());
+            let (Some(_), Some(window), Some(stride), Some(combine)) =
+                (text, window, stride, combine)
+            else {
+                keys.push(None);
+                continue;
+            };
+            keys.push(Some((
+                window_argument("window", window)?,
+                window_argument("stride", stride)?,
+                combine.parse::<WindowCombine>()?,
+            )));
+        }
+
+        let model = get_model(DEFAULT_MODEL)?;
+        let options = model.options().clone();
+        let mut embedded_texts: Vec<Option<Vec<f32>>> = vec![None; texts.len()];
+        for ((window, stride, combine), rows) in group_rows(keys) {
+            let embedder = model.embedder()?;
+            let group_texts = rows.iter().filter_map(|&row| texts[row].clone()).collect();
+            let embeddings = catch_panic("embedding", || {
+                embedder.embed_long(
+                    group_texts,
+                    window,
+                    stride,
+                    combine,
+                    settings::batch_tokens(),
+                    &options,
+                )
+            })?;
+            for (row, embedding) in rows.into_iter().zip(embeddings) {
+                embedded_texts[row] = Some(embedding);
+            }
+        }
+
+        match builtin_model_dim(DEFAULT_MODEL) {
+            Some(dim) => write_array_output(output, &embedded_texts, dim),
+            None => write_list_output(output, &embedded_texts),
+        }
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        vec![
+            ScalarFunctionSignature::exact(
+                vec![LogicalTypeId::Varchar.into()],
+                model_output_type(DEFAULT_MODEL),
+            ),
+            // embed_long(text, window, stride)
+            ScalarFunctionSignature::exact(
+                vec![
+                    LogicalTypeId::Varchar.into(),
+                    LogicalTypeId::Integer.into(),
+                    LogicalTypeId::Integer.into(),
+                ],
+                model_output_type(DEFAULT_MODEL),
+            ),
+            // embed_long(text, window, stride, combine)
+            ScalarFunctionSignature::exact(
+                vec![
+                    LogicalTypeId::Varchar.into(),
+                    LogicalTypeId::Integer.into(),
+                    LogicalTypeId::Integer.into(),
+                    LogicalTypeId::Varchar.into(),
+                ],
+                model_output_type(DEFAULT_MODEL),
+            ),
+        ]
+    }
+}
+
+/// `rerank_score(query, document[, model])`: cross-encoder relevance of the
+/// document to the query, the raw logit of the model's classification head.
+struct RerankScoreFunc;
+
+impl VScalar for RerankScoreFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let queries = read_nullable_strings(input, 0);
+        let documents = read_nullable_strings(input, 1);
+        let model_names = if input.num_columns() > 2 {
+            read_nullable_strings(input, 2)
+        } else {
+            vec![Some(RERANK_MODEL.to_string()); queries.len()]
+        };
+        let model_names = model_names
+            .into_iter()
+            .enumerate()
+            .map(|(row, model_name)| {
+                model_name.filter(|_| queries[row].is_some() && documents[row].is_some())
+            })
+            .collect();
+
+        let scores = map_rows_per_model("scoring", model_names, |embedder, options, rows| {
+            let pairs = rows
+                .iter()
+                .filter_map(|&row| Some((queries[row].clone()?, documents[row].clone()?)))
+                .collect();
+            embedder.score_pairs(pairs, settings::batch_tokens(), options)
+        })?;
+
+        let mut output_vector = output.flat_vector();
+        for (row, score) in scores.into_iter().enumerate() {
+            match score {
+                Some(score) => output_vector.as_mut_slice::<f32>()[row] = score,
+                None => output_vector.set_null(row),
+            }
+        }
+        Ok(())
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        vec![
+            ScalarFunctionSignature::exact(
+                vec![LogicalTypeId::Varchar.into(), LogicalTypeId::Varchar.into()],
+                LogicalTypeId::Float.into(),
+            ),
+            ScalarFunctionSignature::exact(
+                vec![
+                    LogicalTypeId::Varchar.into(),
+                    LogicalTypeId::Varchar.into(),
+                    LogicalTypeId::Varchar.into(),
+                ],
+                LogicalTypeId::Float.into(),
+            ),
+        ]
+    }
+}
+
+/// `quackformers_set(name, value)`: changes a setting for every connection of
+/// the process and returns the value now in effect.
+struct SetFunc;
+
+impl VScalar for SetFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let names = read_nullable_strings(input, 0);
+        let values = read_nullable_strings(input, 1);
+        let output_vector = output.flat_vector();
+        for (row, (name, value)) in names.iter().zip(&values).enumerate() {
+            let (Some(name), Some(value)) = (name, value) else {
+                return Err(EmbeddingError::InvalidArgument(
+                    "quackformers_set takes a non-NULL name and value".to_string(),
+                )
+                .into());
+            };
+            output_vector.insert(row, settings::set(name, value)?.as_str());
+        }
+        Ok(())
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        vec![ScalarFunctionSignature::exact(
+            vec![LogicalTypeId::Varchar.into(), LogicalTypeId::Varchar.into()],
+            LogicalTypeId::Varchar.into(),
+        )]
+    }
+}
+
+/// `quackformers_cache_clear()`: empties the in-memory embedding cache and
+/// resets its counters, returning how many embeddings it dropped. The cache
+/// file, if any, is left alone.
+struct CacheClearFunc;
+
+impl VScalar for CacheClearFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut output_vector = output.flat_vector();
+        let cleared = output_vector.as_mut_slice::<i64>();
+        for cleared in &mut cleared[..input.len()] {
+            // later rows of the same call find the cache already empty
+            *cleared = embedding_cache::clear_cache()? as i64;
+        }
+        Ok(())
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        vec![ScalarFunctionSignature::exact(
+            vec![],
+            LogicalTypeId::Bigint.into(),
+        )]
+    }
+}
+
+struct PreloadFunc;
+
+impl VScalar for PreloadFunc {
     type State = ();
 
     /// # Safety
@@ -140,19 +626,49 @@ impl VScalar for EmbedJinaFunc {
         input: &mut DataChunkHandle,
         output: &mut dyn WritableVector,
     ) -> Result<(), Box<dyn Error>> {
-        generic_embed_invoke(input, output, /*use_jina=*/ true)
+        let model_names = read_nullable_strings(input, 0);
+        let mut output_vector = output.flat_vector();
+        for (row, model_name) in model_names.iter().enumerate() {
+            match model_name {
+                Some(model_name) => {
+                    get_model(model_name)?.embedder()?;
+                    output_vector.as_mut_slice::<bool>()[row] = true;
+                }
+                None => output_vector.set_null(row),
+            }
+        }
+        Ok(())
     }
 
     fn signatures() -> Vec<ScalarFunctionSignature> {
         vec![ScalarFunctionSignature::exact(
             vec![LogicalTypeId::Varchar.into()],
-            LogicalTypeHandle::list(&LogicalTypeId::Float.into()),
+            LogicalTypeId::Boolean.into(),
         )]
     }
 }
 
 const BERT_FUNCTION_NAME: &str = "embed";
 const JINA_FUNCTION_NAME: &str = "embed_jina";
+const EMBED_LONG_FUNCTION_NAME: &str = "embed_long";
+const PRELOAD_FUNCTION_NAME: &str = "quackformers_preload";
+const SET_FUNCTION_NAME: &str = "quackformers_set";
+const CACHE_CLEAR_FUNCTION_NAME: &str = "quackformers_cache_clear";
+const IS_TRUNCATED_FUNCTION_NAME: &str = "is_truncated";
+const TOKENIZE_FUNCTION_NAME: &str = "tokenize";
+const TOKENIZE_PIECES_FUNCTION_NAME: &str = "tokenize_pieces";
+const TOKEN_COUNT_FUNCTION_NAME: &str = "token_count";
+const DETOKENIZE_FUNCTION_NAME: &str = "detokenize";
+const TRUNCATE_TO_TOKENS_FUNCTION_NAME: &str = "truncate_to_tokens";
+const RERANK_SCORE_FUNCTION_NAME: &str = "rerank_score";
+const REGISTER_MODEL_FUNCTION_NAME: &str = "quackformers_register_model";
+const LATE_CHUNKS_FUNCTION_NAME: &str = "embed_late_chunks";
+const CHUNK_TEXT_FUNCTION_NAME: &str = "chunk_text";
+const SPLIT_MARKDOWN_FUNCTION_NAME: &str = "split_markdown";
+const SPLIT_SENTENCES_FUNCTION_NAME: &str = "split_sentences";
+const EMBED_SENTENCES_FUNCTION_NAME: &str = "embed_sentences";
+const RERANK_FUNCTION_NAME: &str = "rerank";
+const CACHE_STATS_FUNCTION_NAME: &str = "quackformers_cache_stats";
 
 #[duckdb_entrypoint_c_api]
 /// # Safety
@@ -160,13 +676,28 @@ const JINA_FUNCTION_NAME: &str = "embed_jina";
 /// The `Connection` must be a valid and open DuckDB connection provided by DuckDB.
 /// Caller must guarantee that DuckDB is properly initialized and not in an error state.
 pub unsafe fn extension_entrypoint(con: Connection) -> Result<(), Box<dyn Error>> {
-    // Force the model + tokenizer to load & JIT right now,
-    // so the *very first* SQL call is fast.
-    Lazy::force(&BERT_EMBEDDER);
-    Lazy::force(&JINA_EMBEDDER);
-    con.register_scalar_function::<EmbedFunc>(BERT_FUNCTION_NAME)
-        .expect("Failed to register embed() function");
-    con.register_scalar_function::<EmbedJinaFunc>(JINA_FUNCTION_NAME)
-        .expect("Failed to register embed_jina() function");
+    // Models load the first time a function uses them (or on quackformers_preload),
+    // so LOAD stays fast and never touches the network.
+    con.register_scalar_function::<EmbedFunc>(BERT_FUNCTION_NAME)?;
+    con.register_scalar_function::<EmbedJinaFunc>(JINA_FUNCTION_NAME)?;
+    con.register_scalar_function::<EmbedLongFunc>(EMBED_LONG_FUNCTION_NAME)?;
+    con.register_scalar_function::<PreloadFunc>(PRELOAD_FUNCTION_NAME)?;
+    con.register_scalar_function::<SetFunc>(SET_FUNCTION_NAME)?;
+    con.register_scalar_function::<CacheClearFunc>(CACHE_CLEAR_FUNCTION_NAME)?;
+    con.register_scalar_function::<IsTruncatedFunc>(IS_TRUNCATED_FUNCTION_NAME)?;
+    con.register_scalar_function::<TokenizeFunc>(TOKENIZE_FUNCTION_NAME)?;
+    con.register_scalar_function::<TokenizePiecesFunc>(TOKENIZE_PIECES_FUNCTION_NAME)?;
+    con.register_scalar_function::<TokenCountFunc>(TOKEN_COUNT_FUNCTION_NAME)?;
+    con.register_scalar_function::<DetokenizeFunc>(DETOKENIZE_FUNCTION_NAME)?;
+    con.register_scalar_function::<TruncateToTokensFunc>(TRUNCATE_TO_TOKENS_FUNCTION_NAME)?;
+    con.register_scalar_function::<RerankScoreFunc>(RERANK_SCORE_FUNCTION_NAME)?;
+    con.register_table_function::<RegisterModelVTab>(REGISTER_MODEL_FUNCTION_NAME)?;
+    con.register_table_function::<LateChunksVTab>(LATE_CHUNKS_FUNCTION_NAME)?;
+    con.register_table_function::<ChunkTextVTab>(CHUNK_TEXT_FUNCTION_NAME)?;
+    con.register_table_function::<SplitMarkdownVTab>(SPLIT_MARKDOWN_FUNCTION_NAME)?;
+    con.register_table_function::<SplitSentencesVTab>(SPLIT_SENTENCES_FUNCTION_NAME)?;
+    con.register_table_function::<EmbedSentencesVTab>(EMBED_SENTENCES_FUNCTION_NAME)?;
+    con.register_table_function::<RerankVTab>(RERANK_FUNCTION_NAME)?;
+    con.register_table_function::<CacheStatsVTab>(CACHE_STATS_FUNCTION_NAME)?;
     Ok(())
 }
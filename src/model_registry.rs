@@ -0,0 +1,197 @@
+use crate::embed_utils::{
+    Embed, EmbedOptions, EmbeddingError, ModelSource, ModelType, TextEmbedder,
+};
+use crate::embedding_cache::embed_cached;
+use crate::settings;
+use candle_core::Device;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+const DEVICE: Device = Device::Cpu;
+
+pub const BERT_MODEL: &str = "bert";
+pub const JINA_MODEL: &str = "jina";
+/// Cross-encoder used by `rerank_score(query, document)`.
+pub const RERANK_MODEL: &str = "ms-marco-minilm";
+
+/// Model used by the single-argument `embed(text)`.
+pub const DEFAULT_MODEL: &str = BERT_MODEL;
+
+fn builtin_model_type(name: &str) -> Option<ModelType> {
+    match name {
+        BERT_MODEL => Some(ModelType::Bert(DEVICE)),
+        JINA_MODEL => Some(ModelType::Jina(DEVICE)),
+        RERANK_MODEL => Some(ModelType::MsMarco(DEVICE)),
+        _ => None,
+    }
+}
+
+/// Dimensions looked up so far, so that the declared SQL type and the vectors
+/// written for it agree even if a model folder's configs change meanwhile.
+static BUILTIN_MODEL_DIMS: Lazy<Mutex<HashMap<String, Option<usize>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Embedding dimension of a built-in model, known without loading any weights.
+pub fn builtin_model_dim(name: &str) -> Option<usize> {
+    let mut dims = BUILTIN_MODEL_DIMS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    *dims.entry(name.to_string()).or_insert_with(|| {
+        builtin_model_type(name).and_then(|model_type| model_type.embedding_dim())
+    })
+}
+
+/// Runs `f`, turning a panic into an error so it never unwinds into DuckDB.
+pub fn catch_panic<T>(
+    action: &str,
+    f: impl FnOnce() -> Result<T, EmbeddingError>,
+) -> Result<T, EmbeddingError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(EmbeddingError::Panic(format!("{action}: {message}")))
+    })
+}
+
+/// Locks `mutex`, reporting poisoning as an error. The poison flag is cleared
+/// so only the call that observes it fails and the next one proceeds.
+pub fn lock_or_error<'a, T>(
+    mutex: &'a Mutex<T>,
+    what: &str,
+) -> Result<MutexGuard<'a, T>, EmbeddingError> {
+    mutex.lock().map_err(|_| {
+        mutex.clear_poison();
+        EmbeddingError::Poisoned(what.to_string())
+    })
+}
+
+/// A named model whose embedder is built the first time it is requested.
+pub struct ModelSlot {
+    name: String,
+    model_type: ModelType,
+    options: EmbedOptions,
+    embedder: OnceCell<TextEmbedder>,
+}
+
+impl ModelSlot {
+    fn new(name: &str, model_type: ModelType, options: EmbedOptions) -> Self {
+        Self {
+            name: name.to_string(),
+            model_type,
+            options,
+            embedder: OnceCell::new(),
+        }
+    }
+
+    /// Pooling and normalization chosen when the model was registered.
+    pub fn options(&self) -> &EmbedOptions {
+        &self.options
+    }
+
+    /// Returns the embedder, loading and warming it up on first use.
+    /// A failed load leaves the slot empty, so the next call tries again.
+    /// The embedder is shared without a lock: concurrent DuckDB threads run
+    /// their forward passes in parallel.
+    pub fn embedder(&self) -> Result<&TextEmbedder, EmbeddingError> {
+        self.embedder.get_or_try_init(|| {
+            catch_panic(&format!("loading model '{}'", self.name), || {
+                let embedder = self.model_type.build_text_embedder()?;
+                // Warm up: do one dummy forward to JIT kernels
+                let dummy = ["hello world".to_string()].to_vec();
+                embedder.embed(dummy, settings::batch_tokens())?;
+                Ok(embedder)
+            })
+        })
+    }
+
+    /// Embeds `texts` with `options`, answering texts seen before from the
+    /// embedding cache.
+    pub fn embed(
+        &self,
+        texts: Vec<String>,
+        options: &EmbedOptions,
+    ) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        let embedder = self.embedder()?;
+        let fingerprint = format!("{}|{:?}", embedder.fingerprint(), options);
+        embed_cached(&fingerprint, texts, |texts| {
+            catch_panic("embedding", || {
+                embedder.embed_with_options(texts, settings::batch_tokens(), options)
+            })
+        })
+    }
+}
+
+/// Models addressable by name from SQL, e.g. `embed(text, 'jina')`.
+pub struct ModelRegistry {
+    models: HashMap<String, Arc<ModelSlot>>,
+}
+
+impl ModelRegistry {
+    fn with_defaults() -> Self {
+        let mut registry = Self {
+            models: HashMap::new(),
+        };
+        for name in [BERT_MODEL, JINA_MODEL, RERANK_MODEL] {
+            if let Some(model_type) = builtin_model_type(name) {
+                registry.register(name, model_type, EmbedOptions::default());
+            }
+        }
+        registry
+    }
+
+    /// Adds (or replaces) a model under `name`. Nothing is loaded until first use.
+    pub fn register(&mut self, name: &str, model_type: ModelType, options: EmbedOptions) {
+        self.models.insert(
+            name.to_string(),
+            Arc::new(ModelSlot::new(name, model_type, options)),
+        );
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<ModelSlot>, EmbeddingError> {
+        self.models.get(name).cloned().ok_or_else(|| {
+            let mut known = self.models.keys().cloned().collect::<Vec<_>>();
+            known.sort();
+            EmbeddingError::ModelTypeError(format!(
+                "Unknown model '{}', registered models: {}",
+                name,
+                known.join(", ")
+            ))
+        })
+    }
+}
+
+pub static MODEL_REGISTRY: Lazy<Mutex<ModelRegistry>> =
+    Lazy::new(|| Mutex::new(ModelRegistry::with_defaults()));
+
+/// Makes the model at `source` available as `name`, replacing any model already
+/// registered under that name. `options` become the model's defaults. Built-in
+/// names can't be taken, their SQL return types are fixed at load time.
+pub fn register_model(
+    name: &str,
+    source: ModelSource,
+    options: EmbedOptions,
+) -> Result<(), EmbeddingError> {
+    if builtin_model_type(name).is_some() {
+        return Err(EmbeddingError::InvalidArgument(format!(
+            "'{}' is a built-in model and can't be registered again",
+            name
+        )));
+    }
+    lock_or_error(Lazy::force(&MODEL_REGISTRY), "model registry")?.register(
+        name,
+        ModelType::Custom(source, DEVICE),
+        options,
+    );
+    Ok(())
+}
+
+/// Looks up `name` in the global registry. The registry lock is released before
+/// the model is loaded, so a slow download never blocks lookups of other models.
+pub fn get_model(name: &str) -> Result<Arc<ModelSlot>, EmbeddingError> {
+    lock_or_error(Lazy::force(&MODEL_REGISTRY), "model registry")?.get(name)
+}
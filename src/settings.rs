@@ -0,0 +1,71 @@
+use crate::embed_utils::EmbeddingError;
+use crate::embedding_cache;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Padded tokens per forward pass unless `batch_tokens` is set, e.g. 32 inputs
+/// of 512 tokens.
+pub const DEFAULT_BATCH_TOKENS: usize = 16384;
+
+/// Embeddings kept in memory unless `cache_size` is set; 0 turns the cache off.
+pub const DEFAULT_CACHE_SIZE: usize = 10000;
+
+const SETTING_NAMES: &[&str] = &["batch_tokens", "cache_size", "cache_path"];
+
+static BATCH_TOKENS: AtomicUsize = AtomicUsize::new(DEFAULT_BATCH_TOKENS);
+static CACHE_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_CACHE_SIZE);
+
+/// Most tokens, padding included, that go through a model in one forward pass.
+pub fn batch_tokens() -> usize {
+    BATCH_TOKENS.load(Ordering::Relaxed)
+}
+
+/// Most embeddings the in-memory cache holds.
+pub fn cache_size() -> usize {
+    CACHE_SIZE.load(Ordering::Relaxed)
+}
+
+/// Changes the setting `name` to `value`, given in its text form, as in
+/// `quackformers_set('batch_tokens', '4096')`. Returns the value now in effect.
+pub fn set(name: &str, value: &str) -> Result<String, EmbeddingError> {
+    match name {
+        "batch_tokens" => {
+            let tokens = parse_positive(name, value)?;
+            BATCH_TOKENS.store(tokens, Ordering::Relaxed);
+            Ok(tokens.to_string())
+        }
+        "cache_size" => {
+            let entries = parse_count(name, value)?;
+            CACHE_SIZE.store(entries, Ordering::Relaxed);
+            embedding_cache::resize_cache(entries)?;
+            Ok(entries.to_string())
+        }
+        "cache_path" => {
+            embedding_cache::set_cache_path(value)?;
+            embedding_cache::cache_path()
+        }
+        _ => Err(EmbeddingError::InvalidArgument(format!(
+            "Unknown setting '{}', known settings: {}",
+            name,
+            SETTING_NAMES.join(", ")
+        ))),
+    }
+}
+
+fn parse_count(name: &str, value: &str) -> Result<usize, EmbeddingError> {
+    value.trim().parse::<usize>().map_err(|_| {
+        EmbeddingError::InvalidArgument(format!(
+            "{} must be a non-negative integer, got '{}'",
+            name, value
+        ))
+    })
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<usize, EmbeddingError> {
+    match value.trim().parse::<usize>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(EmbeddingError::InvalidArgument(format!(
+            "{} must be a positive integer, got '{}'",
+            name, value
+        ))),
+    }
+}
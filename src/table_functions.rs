@@ -0,0 +1,931 @@
+use crate::embed_utils::{
+    EmbedOptions, EmbeddingError, LateChunk, ModelSource, Pooling, Truncation,
+};
+use crate::embedding_cache::{cache_stats, CacheStats};
+use crate::model_registry::{
+    catch_panic, get_model, register_model, DEFAULT_MODEL, JINA_MODEL, RERANK_MODEL,
+};
+use crate::settings;
+use crate::text_split::{
+    char_slice, char_to_byte_offsets, chunk_by_tokens, split_markdown, split_sentences,
+    MarkdownChunk, TextChunk,
+};
+use duckdb::{
+    core::{DataChunkHandle, Inserter, LogicalTypeHandle, LogicalTypeId},
+    ffi,
+    vtab::{BindInfo, InitInfo, TableFunctionInfo, VTab, Value},
+};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Rows DuckDB accepts per output chunk (`STANDARD_VECTOR_SIZE`).
+const MAX_ROWS_PER_CHUNK: usize = 2048;
+
+const DEFAULT_CHUNK_TOKENS: usize = 256;
+const DEFAULT_CHUNK_OVERLAP: usize = 32;
+
+#[repr(C)]
+pub struct RegisterModelBindData {
+    name: String,
+    source: String,
+    revision: Option<String>,
+    options: EmbedOptions,
+}
+
+#[repr(C)]
+pub struct RegisterModelInitData {
+    done: AtomicBool,
+}
+
+/// `quackformers_register_model(name, source, revision := ..., pooling := ..., normalize := ...,
+/// truncation := ..., max_tokens := ...)`: makes a Hugging Face Hub repo or a local
+/// folder usable as `embed(text, name)`. `pooling` and `normalize` replace what the
+/// model declares, `truncation` and `max_tokens` control how long inputs are cut. The model is only
+/// fetched and loaded the first time it is used.
+pub struct RegisterModelVTab;
+
+impl VTab for RegisterModelVTab {
+    type InitData = RegisterModelInitData;
+    type BindData = RegisterModelBindData;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        bind.add_result_column("name", LogicalTypeId::Varchar.into());
+        bind.add_result_column("source", LogicalTypeId::Varchar.into());
+        bind.add_result_column("revision", LogicalTypeId::Varchar.into());
+        let pooling = named_parameter(bind, "pooling")?
+            .map(|value| value.parse::<Pooling>())
+            .transpose()?;
+        let normalize = named_parameter(bind, "normalize")?
+            .map(|value| parse_bool(&value))
+            .transpose()?;
+        let truncation = named_parameter(bind, "truncation")?
+            .map(|value| value.parse::<Truncation>())
+            .transpose()?;
+        let max_tokens = named_parameter(bind, "max_tokens")?
+            .map(|value| parse_max_tokens(&value))
+            .transpose()?;
+        Ok(RegisterModelBindData {
+            name: required_parameter(bind, 0, "name")?,
+            source: required_parameter(bind, 1, "source")?,
+            revision: named_parameter(bind, "revision")?,
+            options: EmbedOptions {
+                pooling,
+                normalize,
+                truncation,
+                max_tokens,
+            },
+        })
+    }
+
+    fn init(_: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(RegisterModelInitData {
+            done: AtomicBool::new(false),
+        })
+    }
+
+    fn func(
+        func: &TableFunctionInfo<Self>,
+        output: &mut DataChunkHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        let init_data = func.get_init_data();
+        let bind_data = func.get_bind_data();
+
+        if init_data.done.swap(true, Ordering::Relaxed) {
+            output.set_len(0);
+            return Ok(());
+        }
+
+        // registered at execution rather than bind, so EXPLAIN or a re-bind
+        // never changes the registry
+        let source = ModelSource::parse(&bind_data.source, bind_data.revision.clone())?;
+        register_model(&bind_data.name, source, bind_data.options.clone())?;
+
+        output.flat_vector(0).insert(0, bind_data.name.as_str());
+        output.flat_vector(1).insert(0, bind_data.source.as_str());
+        match &bind_data.revision {
+            Some(revision) => output.flat_vector(2).insert(0, revision.as_str()),
+            None => output.flat_vector(2).set_null(0),
+        }
+        output.set_len(1);
+        Ok(())
+    }
+
+    fn parameters() -> Option<Vec<LogicalTypeHandle>> {
+        Some(vec![
+            LogicalTypeId::Varchar.into(),
+            LogicalTypeId::Varchar.into(),
+        ])
+    }
+
+    fn named_parameters() -> Option<Vec<(String, LogicalTypeHandle)>> {
+        Some(vec![
+            ("revision".to_string(), LogicalTypeId::Varchar.into()),
+            ("pooling".to_string(), LogicalTypeId::Varchar.into()),
+            ("normalize".to_string(), LogicalTypeId::Boolean.into()),
+            ("truncation".to_string(), LogicalTypeId::Varchar.into()),
+            ("max_tokens".to_string(), LogicalTypeId::Bigint.into()),
+        ])
+    }
+}
+
+/// Rows a table function computes on its first call, then hands out in
+/// output-sized pages.
+pub struct PagedRows<T> {
+    state: Mutex<(Option<Vec<T>>, usize)>,
+}
+
+impl<T> PagedRows<T> {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new((None, 0)),
+        }
+    }
+
+    /// Builds the rows with `compute` on first use, then passes the next page to
+    /// `write` along with the index of its first row. Returns the page's length,
+    /// 0 once every row was written.
+    fn next_page(
+        &self,
+        compute: impl FnOnce() -> Result<Vec<T>, Box<dyn Error>>,
+        write: impl FnOnce(usize, &[T]) -> Result<(), Box<dyn Error>>,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| EmbeddingError::Poisoned("table function rows".to_string()))?;
+        let (rows, next) = &mut *state;
+        if rows.is_none() {
+            *rows = Some(compute()?);
+        }
+        let rows = rows.as_deref().unwrap_or_default();
+        let page = &rows[*next..rows.len().min(*next + MAX_ROWS_PER_CHUNK)];
+        write(*next, page)?;
+        *next += page.len();
+        Ok(page.len())
+    }
+}
+
+/// Writes the `chunk_text`, `start_char` and `end_char` columns shared by the
+/// splitting functions, starting at column `first_column`.
+fn write_spans(
+    output: &DataChunkHandle,
+    first_column: usize,
+    text: &str,
+    spans: impl Iterator<Item = (usize, usize)>,
+) {
+    let byte_offsets = char_to_byte_offsets(text);
+    for (row, (start_char, end_char)) in spans.enumerate() {
+        let chunk_text = char_slice(text, &byte_offsets, start_char, end_char);
+        output.flat_vector(first_column).insert(row, chunk_text);
+        output.flat_vector(first_column + 1).as_mut_slice::<i64>()[row] = start_char as i64;
+        output.flat_vector(first_column + 2).as_mut_slice::<i64>()[row] = end_char as i64;
+    }
+}
+
+/// Writes row numbers `first_index..` into the BIGINT column `column`.
+fn write_indices(output: &DataChunkHandle, column: usize, first_index: usize, len: usize) {
+    let mut index_vector = output.flat_vector(column);
+    for (row, index) in index_vector.as_mut_slice::<i64>()[..len]
+        .iter_mut()
+        .enumerate()
+    {
+        *index = (first_index + row) as i64;
+    }
+}
+
+/// Writes one `FLOAT[]` list per row into column `column`.
+fn write_embeddings<'a>(
+    output: &DataChunkHandle,
+    column: usize,
+    embeddings: impl Iterator<Item = &'a [f32]> + Clone,
+) {
+    let total_len: usize = embeddings.clone().map(<[f32]>::len).sum();
+    let mut embedding_vector = output.list_vector(column);
+    let mut embedding_child = embedding_vector.child(total_len);
+    let embedding_values = embedding_child.as_mut_slice_with_len::<f32>(total_len);
+    let mut offset = 0;
+    for (row, embedding) in embeddings.enumerate() {
+        embedding_values[offset..offset + embedding.len()].copy_from_slice(embedding);
+        embedding_vector.set_entry(row, offset, embedding.len());
+        offset += embedding.len();
+    }
+    embedding_vector.set_len(total_len);
+}
+
+#[repr(C)]
+pub struct LateChunksBindData {
+    /// `None` for a NULL text, which has no chunks.
+    text: Option<String>,
+    chunk_size: usize,
+    overlap: usize,
+    model: String,
+}
+
+/// `embed_late_chunks(text, chunk_size, overlap, model := 'jina')`: one forward
+/// pass over the whole text, one row per chunk of `chunk_size` tokens with its
+/// character span and a vector pooled from the document-wide token states.
+pub struct LateChunksVTab;
+
+impl VTab for LateChunksVTab {
+    type InitData = PagedRows<LateChunk>;
+    type BindData = LateChunksBindData;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        bind.add_result_column("chunk_index", LogicalTypeId::Bigint.into());
+        bind.add_result_column("chunk_text", LogicalTypeId::Varchar.into());
+        bind.add_result_column("start_char", LogicalTypeId::Bigint.into());
+        bind.add_result_column("end_char", LogicalTypeId::Bigint.into());
+        bind.add_result_column(
+            "embedding",
+            LogicalTypeHandle::list(&LogicalTypeId::Float.into()),
+        );
+        Ok(LateChunksBindData {
+            text: nullable_parameter(bind, 0),
+            chunk_size: parse_token_count(
+                "chunk_size",
+                &required_parameter(bind, 1, "chunk_size")?,
+            )?,
+            overlap: parse_token_count("overlap", &required_parameter(bind, 2, "overlap")?)?,
+            model: named_parameter(bind, "model")?.unwrap_or_else(|| JINA_MODEL.to_string()),
+        })
+    }
+
+    fn init(_: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(PagedRows::new())
+    }
+
+    fn func(
+        func: &TableFunctionInfo<Self>,
+        output: &mut DataChunkHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        let bind_data = func.get_bind_data();
+        let Some(text) = bind_data.text.as_deref() else {
+            output.set_len(0);
+            return Ok(());
+        };
+        let compute = || -> Result<Vec<LateChunk>, Box<dyn Error>> {
+            let model = get_model(&bind_data.model)?;
+            let embedder = model.embedder()?;
+            Ok(catch_panic("embedding", || {
+                embedder.embed_late_chunks(
+                    text,
+                    bind_data.chunk_size,
+                    bind_data.overlap,
+                    model.options(),
+                )
+            })?)
+        };
+        let write = |first_index: usize, chunks: &[LateChunk]| -> Result<(), Box<dyn Error>> {
+            write_indices(output, 0, first_index, chunks.len());
+            write_spans(
+                output,
+                1,
+                text,
+                chunks
+                    .iter()
+                    .map(|chunk| (chunk.start_char, chunk.end_char)),
+            );
+
+            write_embeddings(output, 4, chunks.iter().map(|chunk| &chunk.embedding[..]));
+            Ok(())
+        };
+        let len = func.get_init_data().next_page(compute, write)?;
+        output.set_len(len);
+        Ok(())
+    }
+
+    fn parameters() -> Option<Vec<LogicalTypeHandle>> {
+        Some(vec![
+            LogicalTypeId::Varchar.into(),
+            LogicalTypeId::Bigint.into(),
+            LogicalTypeId::Bigint.into(),
+        ])
+    }
+
+    fn named_parameters() -> Option<Vec<(String, LogicalTypeHandle)>> {
+        Some(vec![("model".to_string(), LogicalTypeId::Varchar.into())])
+    }
+}
+
+#[repr(C)]
+pub struct ChunkTextBindData {
+    /// `None` for a NULL text, which has no chunks.
+    text: Option<String>,
+    model: String,
+    max_tokens: usize,
+    overlap: usize,
+}
+
+/// `chunk_text(text, model := 'bert', max_tokens := 256, overlap := 32)`: splits
+/// a text into chunks that fit `max_tokens` of the model's own tokenizer, special
+/// tokens included, so each chunk embeds without truncation.
+pub struct ChunkTextVTab;
+
+impl VTab for ChunkTextVTab {
+    type InitData = PagedRows<TextChunk>;
+    type BindData = ChunkTextBindData;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        bind.add_result_column("chunk_index", LogicalTypeId::Bigint.into());
+        bind.add_result_column("chunk_text", LogicalTypeId::Varchar.into());
+        bind.add_result_column("start_char", LogicalTypeId::Bigint.into());
+        bind.add_result_column("end_char", LogicalTypeId::Bigint.into());
+        Ok(ChunkTextBindData {
+            text: nullable_parameter(bind, 0),
+            model: model_parameter(bind)?,
+            max_tokens: max_tokens_parameter(bind)?,
+            overlap: named_parameter(bind, "overlap")?
+                .map(|value| parse_token_count("overlap", &value))
+                .transpose()?
+                .unwrap_or(DEFAULT_CHUNK_OVERLAP),
+        })
+    }
+
+    fn init(_: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(PagedRows::new())
+    }
+
+    fn func(
+        func: &TableFunctionInfo<Self>,
+        output: &mut DataChunkHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        let bind_data = func.get_bind_data();
+        let Some(text) = bind_data.text.as_deref() else {
+            output.set_len(0);
+            return Ok(());
+        };
+        let compute = || -> Result<Vec<TextChunk>, Box<dyn Error>> {
+            let (offsets, budget) = token_offsets(&bind_data.model, text, bind_data.max_tokens)?;
+            Ok(chunk_by_tokens(text, &offsets, budget, bind_data.overlap)?)
+        };
+        let write = |first_index: usize, chunks: &[TextChunk]| -> Result<(), Box<dyn Error>> {
+            write_indices(output, 0, first_index, chunks.len());
+            write_spans(
+                output,
+                1,
+                text,
+                chunks
+                    .iter()
+                    .map(|chunk| (chunk.start_char, chunk.end_char)),
+            );
+            Ok(())
+        };
+        let len = func.get_init_data().next_page(compute, write)?;
+        output.set_len(len);
+        Ok(())
+    }
+
+    fn parameters() -> Option<Vec<LogicalTypeHandle>> {
+        Some(vec![LogicalTypeId::Varchar.into()])
+    }
+
+    fn named_parameters() -> Option<Vec<(String, LogicalTypeHandle)>> {
+        Some(vec![
+            ("model".to_string(), LogicalTypeId::Varchar.into()),
+            ("max_tokens".to_string(), LogicalTypeId::Bigint.into()),
+            ("overlap".to_string(), LogicalTypeId::Bigint.into()),
+        ])
+    }
+}
+
+#[repr(C)]
+pub struct SplitMarkdownBindData {
+    /// `None` for a NULL text, which has no chunks.
+    text: Option<String>,
+    model: String,
+    max_tokens: usize,
+}
+
+/// `split_markdown(text, max_tokens, model := 'bert')`: splits a Markdown document
+/// along its headings, keeping code blocks and tables whole, with each chunk's
+/// heading path. Sections over `max_tokens` are split between blocks.
+pub struct SplitMarkdownVTab;
+
+impl VTab for SplitMarkdownVTab {
+    type InitData = PagedRows<MarkdownChunk>;
+    type BindData = SplitMarkdownBindData;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        bind.add_result_column("chunk_index", LogicalTypeId::Bigint.into());
+        bind.add_result_column(
+            "heading_path",
+            LogicalTypeHandle::list(&LogicalTypeId::Varchar.into()),
+        );
+        bind.add_result_column("chunk_text", LogicalTypeId::Varchar.into());
+        bind.add_result_column("start_char", LogicalTypeId::Bigint.into());
+        bind.add_result_column("end_char", LogicalTypeId::Bigint.into());
+        Ok(SplitMarkdownBindData {
+            text: nullable_parameter(bind, 0),
+            model: model_parameter(bind)?,
+            max_tokens: parse_max_tokens(&required_parameter(bind, 1, "max_tokens")?)?,
+        })
+    }
+
+    fn init(_: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(PagedRows::new())
+    }
+
+    fn func(
+        func: &TableFunctionInfo<Self>,
+        output: &mut DataChunkHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        let bind_data = func.get_bind_data();
+        let Some(text) = bind_data.text.as_deref() else {
+            output.set_len(0);
+            return Ok(());
+        };
+        let compute = || -> Result<Vec<MarkdownChunk>, Box<dyn Error>> {
+            let (offsets, budget) = token_offsets(&bind_data.model, text, bind_data.max_tokens)?;
+            Ok(split_markdown(text, &offsets, budget)?)
+        };
+        let write = |first_index: usize, chunks: &[MarkdownChunk]| -> Result<(), Box<dyn Error>> {
+            write_indices(output, 0, first_index, chunks.len());
+
+            let total_len: usize = chunks.iter().map(|chunk| chunk.heading_path.len()).sum();
+            let mut path_vector = output.list_vector(1);
+            let path_child = path_vector.child(total_len);
+            let mut offset = 0;
+            for (row, chunk) in chunks.iter().enumerate() {
+                for (i, heading) in chunk.heading_path.iter().enumerate() {
+                    path_child.insert(offset + i, heading.as_str());
+                }
+                path_vector.set_entry(row, offset, chunk.heading_path.len());
+                offset += chunk.heading_path.len();
+            }
+            path_vector.set_len(total_len);
+
+            write_spans(
+                output,
+                2,
+                text,
+                chunks
+                    .iter()
+                    .map(|chunk| (chunk.start_char, chunk.end_char)),
+            );
+            Ok(())
+        };
+        let len = func.get_init_data().next_page(compute, write)?;
+        output.set_len(len);
+        Ok(())
+    }
+
+    fn parameters() -> Option<Vec<LogicalTypeHandle>> {
+        Some(vec![
+            LogicalTypeId::Varchar.into(),
+            LogicalTypeId::Bigint.into(),
+        ])
+    }
+
+    fn named_parameters() -> Option<Vec<(String, LogicalTypeHandle)>> {
+        Some(vec![("model".to_string(), LogicalTypeId::Varchar.into())])
+    }
+}
+
+#[repr(C)]
+pub struct SplitSentencesBindData {
+    /// `None` for a NULL text, which has no sentences.
+    text: Option<String>,
+}
+
+/// `split_sentences(text)`: one row per sentence with its character span.
+pub struct SplitSentencesVTab;
+
+impl VTab for SplitSentencesVTab {
+    type InitData = PagedRows<TextChunk>;
+    type BindData = SplitSentencesBindData;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        bind.add_result_column("sentence_index", LogicalTypeId::Bigint.into());
+        bind.add_result_column("sentence", LogicalTypeId::Varchar.into());
+        bind.add_result_column("start_char", LogicalTypeId::Bigint.into());
+        bind.add_result_column("end_char", LogicalTypeId::Bigint.into());
+        Ok(SplitSentencesBindData {
+            text: nullable_parameter(bind, 0),
+        })
+    }
+
+    fn init(_: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(PagedRows::new())
+    }
+
+    fn func(
+        func: &TableFunctionInfo<Self>,
+        output: &mut DataChunkHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(text) = func.get_bind_data().text.as_deref() else {
+            output.set_len(0);
+            return Ok(());
+        };
+        let compute = || -> Result<Vec<TextChunk>, Box<dyn Error>> { Ok(split_sentences(text)) };
+        let write = |first_index: usize, sentences: &[TextChunk]| -> Result<(), Box<dyn Error>> {
+            write_indices(output, 0, first_index, sentences.len());
+            write_spans(
+                output,
+                1,
+                text,
+                sentences
+                    .iter()
+                    .map(|sentence| (sentence.start_char, sentence.end_char)),
+            );
+            Ok(())
+        };
+        let len = func.get_init_data().next_page(compute, write)?;
+        output.set_len(len);
+        Ok(())
+    }
+
+    fn parameters() -> Option<Vec<LogicalTypeHandle>> {
+        Some(vec![LogicalTypeId::Varchar.into()])
+    }
+}
+
+#[repr(C)]
+pub struct EmbedSentencesBindData {
+    /// `None` for a NULL text, which has no sentences.
+    text: Option<String>,
+    model: String,
+}
+
+/// A sentence's span and its embedding.
+type EmbeddedSentence = (TextChunk, Vec<f32>);
+
+/// `embed_sentences(text, model)`: the rows of `split_sentences(text)` plus an
+/// `embedding` column, all sentences embedded in one batch.
+pub struct EmbedSentencesVTab;
+
+impl VTab for EmbedSentencesVTab {
+    type InitData = PagedRows<EmbeddedSentence>;
+    type BindData = EmbedSentencesBindData;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        bind.add_result_column("sentence_index", LogicalTypeId::Bigint.into());
+        bind.add_result_column("sentence", LogicalTypeId::Varchar.into());
+        bind.add_result_column("start_char", LogicalTypeId::Bigint.into());
+        bind.add_result_column("end_char", LogicalTypeId::Bigint.into());
+        bind.add_result_column(
+            "embedding",
+            LogicalTypeHandle::list(&LogicalTypeId::Float.into()),
+        );
+        Ok(EmbedSentencesBindData {
+            text: nullable_parameter(bind, 0),
+            model: required_parameter(bind, 1, "model")?,
+        })
+    }
+
+    fn init(_: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(PagedRows::new())
+    }
+
+    fn func(
+        func: &TableFunctionInfo<Self>,
+        output: &mut DataChunkHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        let bind_data = func.get_bind_data();
+        let Some(text) = bind_data.text.as_deref() else {
+            output.set_len(0);
+            return Ok(());
+        };
+        let compute = || -> Result<Vec<EmbeddedSentence>, Box<dyn Error>> {
+            let sentences = split_sentences(text);
+            let byte_offsets = char_to_byte_offsets(text);
+            let texts = sentences
+                .iter()
+                .map(|sentence| {
+                    char_slice(text, &byte_offsets, sentence.start_char, sentence.end_char)
+                        .to_string()
+                })
+                .collect();
+            let model = get_model(&bind_data.model)?;
+            let embeddings = model.embed(texts, model.options())?;
+            Ok(sentences.into_iter().zip(embeddings).collect())
+        };
+        let write = |first_index: usize, rows: &[EmbeddedSentence]| -> Result<(), Box<dyn Error>> {
+            write_indices(output, 0, first_index, rows.len());
+            write_spans(
+                output,
+                1,
+                text,
+                rows.iter()
+                    .map(|(sentence, _)| (sentence.start_char, sentence.end_char)),
+            );
+            write_embeddings(output, 4, rows.iter().map(|(_, embedding)| &embedding[..]));
+            Ok(())
+        };
+        let len = func.get_init_data().next_page(compute, write)?;
+        output.set_len(len);
+        Ok(())
+    }
+
+    fn parameters() -> Option<Vec<LogicalTypeHandle>> {
+        Some(vec![
+            LogicalTypeId::Varchar.into(),
+            LogicalTypeId::Varchar.into(),
+        ])
+    }
+}
+
+#[repr(C)]
+pub struct CacheStatsBindData {}
+
+/// `quackformers_cache_stats()`: one row with the embedding cache's size, its
+/// capacity, hits and misses since the last clear, the hit rate (NULL before
+/// any lookup) and the number of embeddings in the cache file (NULL without one).
+pub struct CacheStatsVTab;
+
+impl VTab for CacheStatsVTab {
+    type InitData = PagedRows<CacheStats>;
+    type BindData = CacheStatsBindData;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        bind.add_result_column("entries", LogicalTypeId::Bigint.into());
+        bind.add_result_column("capacity", LogicalTypeId::Bigint.into());
+        bind.add_result_column("hits", LogicalTypeId::Bigint.into());
+        bind.add_result_column("misses", LogicalTypeId::Bigint.into());
+        bind.add_result_column("hit_rate", LogicalTypeId::Double.into());
+        bind.add_result_column("disk_entries", LogicalTypeId::Bigint.into());
+        Ok(CacheStatsBindData {})
+    }
+
+    fn init(_: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(PagedRows::new())
+    }
+
+    fn func(
+        func: &TableFunctionInfo<Self>,
+        output: &mut DataChunkHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        let compute = || -> Result<Vec<CacheStats>, Box<dyn Error>> { Ok(vec![cache_stats()?]) };
+        let write = |_: usize, rows: &[CacheStats]| -> Result<(), Box<dyn Error>> {
+            for (row, stats) in rows.iter().enumerate() {
+                output.flat_vector(0).as_mut_slice::<i64>()[row] = stats.entries as i64;
+                output.flat_vector(1).as_mut_slice::<i64>()[row] = stats.capacity as i64;
+                output.flat_vector(2).as_mut_slice::<i64>()[row] = stats.hits as i64;
+                output.flat_vector(3).as_mut_slice::<i64>()[row] = stats.misses as i64;
+                let lookups = stats.hits + stats.misses;
+                if lookups == 0 {
+                    output.flat_vector(4).set_null(row);
+                } else {
+                    output.flat_vector(4).as_mut_slice::<f64>()[row] =
+                        stats.hits as f64 / lookups as f64;
+                }
+                match stats.disk_entries {
+                    Some(entries) => {
+                        output.flat_vector(5).as_mut_slice::<i64>()[row] = entries as i64
+                    }
+                    None => output.flat_vector(5).set_null(row),
+                }
+            }
+            Ok(())
+        };
+        let len = func.get_init_data().next_page(compute, write)?;
+        output.set_len(len);
+        Ok(())
+    }
+}
+
+/// One entry of the `candidates` passed to `rerank`.
+pub struct RerankCandidate {
+    /// Position in the `candidates` array.
+    index: usize,
+    id: Option<String>,
+    text: String,
+}
+
+/// Reads `candidates` as a JSON array whose items are either strings or objects
+/// with a `text` and an optional `id`, e.g. `to_json(list({'id': id, 'text': body}))`.
+fn parse_candidates(candidates: &str) -> Result<Vec<RerankCandidate>, EmbeddingError> {
+    let invalid = || {
+        EmbeddingError::InvalidArgument(
+            "candidates must be a JSON array of strings or of {\"id\": ..., \"text\": ...} objects"
+                .to_string(),
+        )
+    };
+    let items =
+        serde_json::from_str::<Vec<serde_json::Value>>(candidates).map_err(|_| invalid())?;
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let (id, text) = match item {
+                serde_json::Value::String(text) => (None, text),
+                serde_json::Value::Object(mut fields) => {
+                    let Some(serde_json::Value::String(text)) = fields.remove("text") else {
+                        return Err(invalid());
+                    };
+                    let id = match fields.remove("id") {
+                        None | Some(serde_json::Value::Null) => None,
+                        Some(serde_json::Value::String(id)) => Some(id),
+                        Some(id) => Some(id.to_string()),
+                    };
+                    (id, text)
+                }
+                _ => return Err(invalid()),
+            };
+            Ok(RerankCandidate { index, id, text })
+        })
+        .collect()
+}
+
+#[repr(C)]
+pub struct RerankBindData {
+    /// `None` for a NULL query, which ranks nothing.
+    query: Option<String>,
+    candidates: Vec<RerankCandidate>,
+    top_n: Option<usize>,
+    model: String,
+}
+
+/// `rerank(query, candidates, top_n := ..., model := 'ms-marco-minilm')`: scores
+/// every candidate against the query with a cross-encoder, in batches of at most
+/// `batch_tokens`, and returns the best `top_n` (all by default), most relevant first.
+pub struct RerankVTab;
+
+impl VTab for RerankVTab {
+    type InitData = PagedRows<(usize, f32)>;
+    type BindData = RerankBindData;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        bind.add_result_column("rank", LogicalTypeId::Bigint.into());
+        bind.add_result_column("candidate_index", LogicalTypeId::Bigint.into());
+        bind.add_result_column("id", LogicalTypeId::Varchar.into());
+        bind.add_result_column("text", LogicalTypeId::Varchar.into());
+        bind.add_result_column("score", LogicalTypeId::Float.into());
+        Ok(RerankBindData {
+            query: nullable_parameter(bind, 0),
+            candidates: parse_candidates(&required_parameter(bind, 1, "candidates")?)?,
+            top_n: named_parameter(bind, "top_n")?
+                .map(|value| parse_token_count("top_n", &value))
+                .transpose()?,
+            model: named_parameter(bind, "model")?.unwrap_or_else(|| RERANK_MODEL.to_string()),
+        })
+    }
+
+    fn init(_: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(PagedRows::new())
+    }
+
+    fn func(
+        func: &TableFunctionInfo<Self>,
+        output: &mut DataChunkHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        let bind_data = func.get_bind_data();
+        let Some(query) = bind_data.query.as_deref() else {
+            output.set_len(0);
+            return Ok(());
+        };
+        let candidates = &bind_data.candidates;
+        // (position in `candidates`, score), best first
+        let compute = || -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+            if candidates.is_empty() {
+                return Ok(Vec::new());
+            }
+            let pairs = candidates
+                .iter()
+                .map(|candidate| (query.to_string(), candidate.text.clone()))
+                .collect();
+            let model = get_model(&bind_data.model)?;
+            let embedder = model.embedder()?;
+            let scores = catch_panic("scoring", || {
+                embedder.score_pairs(pairs, settings::batch_tokens(), model.options())
+            })?;
+            let mut ranked = scores.into_iter().enumerate().collect::<Vec<_>>();
+            ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+            ranked.truncate(bind_data.top_n.unwrap_or(ranked.len()));
+            Ok(ranked)
+        };
+        let write = |first_rank: usize, ranked: &[(usize, f32)]| -> Result<(), Box<dyn Error>> {
+            write_indices(output, 0, first_rank, ranked.len());
+            for (row, &(position, score)) in ranked.iter().enumerate() {
+                let candidate = &candidates[position];
+                output.flat_vector(1).as_mut_slice::<i64>()[row] = candidate.index as i64;
+                match &candidate.id {
+                    Some(id) => output.flat_vector(2).insert(row, id.as_str()),
+                    None => output.flat_vector(2).set_null(row),
+                }
+                output.flat_vector(3).insert(row, candidate.text.as_str());
+                output.flat_vector(4).as_mut_slice::<f32>()[row] = score;
+            }
+            Ok(())
+        };
+        let len = func.get_init_data().next_page(compute, write)?;
+        output.set_len(len);
+        Ok(())
+    }
+
+    fn parameters() -> Option<Vec<LogicalTypeHandle>> {
+        Some(vec![
+            LogicalTypeId::Varchar.into(),
+            LogicalTypeId::Varchar.into(),
+        ])
+    }
+
+    fn named_parameters() -> Option<Vec<(String, LogicalTypeHandle)>> {
+        Some(vec![
+            ("top_n".to_string(), LogicalTypeId::Bigint.into()),
+            ("model".to_string(), LogicalTypeId::Varchar.into()),
+        ])
+    }
+}
+
+// `Value` is nothing but its `duckdb_value` pointer, see `value_is_null`
+const _: () = assert!(size_of::<Value>() == size_of::<ffi::duckdb_value>());
+
+/// Whether a parameter is NULL. duckdb-rs's `Value` can't tell and DuckDB
+/// throws on reading a NULL value as text, so this asks the C API directly.
+fn value_is_null(value: &Value) -> bool {
+    unsafe {
+        ffi::duckdb_is_null_value(std::mem::transmute_copy::<Value, ffi::duckdb_value>(value))
+    }
+}
+
+/// Positional parameter `index` in its text form, `None` when it is NULL.
+fn nullable_parameter(bind: &BindInfo, index: u64) -> Option<String> {
+    let value = bind.get_parameter(index);
+    (!value_is_null(&value)).then(|| value.to_string())
+}
+
+/// Positional parameter `index` in its text form, an error naming it when NULL.
+fn required_parameter(bind: &BindInfo, index: u64, name: &str) -> Result<String, EmbeddingError> {
+    nullable_parameter(bind, index)
+        .ok_or_else(|| EmbeddingError::InvalidArgument(format!("{} must not be NULL", name)))
+}
+
+/// Named parameter `name` in its text form, `None` when absent and an error
+/// when NULL.
+fn named_parameter(bind: &BindInfo, name: &str) -> Result<Option<String>, EmbeddingError> {
+    bind.get_named_parameter(name)
+        .map(|value| {
+            if value_is_null(&value) {
+                return Err(EmbeddingError::InvalidArgument(format!(
+                    "{} must not be NULL",
+                    name
+                )));
+            }
+            Ok(value.to_string())
+        })
+        .transpose()
+}
+
+/// The `model` named parameter, the default model when absent.
+fn model_parameter(bind: &BindInfo) -> Result<String, EmbeddingError> {
+    Ok(named_parameter(bind, "model")?.unwrap_or_else(|| DEFAULT_MODEL.to_string()))
+}
+
+/// The `max_tokens` named parameter, `DEFAULT_CHUNK_TOKENS` when absent.
+fn max_tokens_parameter(bind: &BindInfo) -> Result<usize, EmbeddingError> {
+    Ok(named_parameter(bind, "max_tokens")?
+        .map(|value| parse_max_tokens(&value))
+        .transpose()?
+        .unwrap_or(DEFAULT_CHUNK_TOKENS))
+}
+
+/// Token spans of `text` under `model`'s tokenizer, and how many tokens of text
+/// fit `max_tokens` once special tokens are added.
+fn token_offsets(
+    model: &str,
+    text: &str,
+    max_tokens: usize,
+) -> Result<(Vec<(usize, usize)>, usize), EmbeddingError> {
+    let model = get_model(model)?;
+    let embedder = model.embedder()?;
+    catch_panic("tokenizing", || {
+        Ok((
+            embedder.token_offsets(text)?,
+            embedder.text_budget(Some(max_tokens)),
+        ))
+    })
+}
+
+/// Reads a non-negative token count from its text form.
+fn parse_token_count(name: &str, value: &str) -> Result<usize, EmbeddingError> {
+    value.parse::<usize>().map_err(|_| {
+        EmbeddingError::InvalidArgument(format!(
+            "{} must be a non-negative integer, got '{}'",
+            name, value
+        ))
+    })
+}
+
+/// Reads a BOOLEAN named parameter from its text form.
+fn parse_bool(value: &str) -> Result<bool, EmbeddingError> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(EmbeddingError::InvalidArgument(format!(
+            "Expected a boolean, got '{}'",
+            value
+        ))),
+    }
+}
+
+/// Reads a positive token limit from its text form.
+fn parse_max_tokens(value: &str) -> Result<usize, EmbeddingError> {
+    match value.parse::<usize>() {
+        Ok(max_tokens) if max_tokens > 0 => Ok(max_tokens),
+        _ => Err(EmbeddingError::InvalidArgument(format!(
+            "max_tokens must be a positive integer, got '{}'",
+            value
+        ))),
+    }
+}
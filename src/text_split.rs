@@ -0,0 +1,490 @@
+use crate::embed_utils::EmbeddingError;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A piece of a text, `[start_char, end_char)` in characters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextChunk {
+    pub start_char: usize,
+    pub end_char: usize,
+}
+
+/// The characters `[start_char, end_char)` of `text`, given `byte_offsets`
+/// from [`char_to_byte_offsets`].
+pub fn char_slice<'a>(
+    text: &'a str,
+    byte_offsets: &[usize],
+    start_char: usize,
+    end_char: usize,
+) -> &'a str {
+    &text[byte_offsets[start_char]..byte_offsets[end_char]]
+}
+
+/// Byte offset of every character of `text`, plus `text.len()` at the end, so
+/// character spans can be sliced out of the string.
+pub fn char_to_byte_offsets(text: &str) -> Vec<usize> {
+    text.char_indices()
+        .map(|(byte, _)| byte)
+        .chain(std::iter::once(text.len()))
+        .collect()
+}
+
+/// Groups tokens into chunks of at most `max_tokens`, consecutive chunks sharing
+/// `overlap` tokens. `offsets` are the `(start, end)` character spans of the
+/// tokens of `text`. A chunk ends early, at the last sentence or line break in
+/// its second half, rather than cutting a sentence in two.
+pub fn chunk_by_tokens(
+    text: &str,
+    offsets: &[(usize, usize)],
+    max_tokens: usize,
+    overlap: usize,
+) -> Result<Vec<TextChunk>, EmbeddingError> {
+    if max_tokens == 0 || overlap >= max_tokens {
+        return Err(EmbeddingError::InvalidArgument(format!(
+            "max_tokens must leave room for text and be larger than overlap, got {} tokens and overlap {}",
+            max_tokens, overlap
+        )));
+    }
+    let chars = text.chars().collect::<Vec<_>>();
+    // whether a chunk may end right after token `index`
+    let ends_sentence = |index: usize| {
+        let end = offsets[index].1;
+        let next_start = offsets.get(index + 1).map_or(chars.len(), |next| next.0);
+        let last_char = end.checked_sub(1).and_then(|last| chars.get(last));
+        matches!(last_char, Some('.' | '!' | '?'))
+            || chars
+                .get(end..next_start)
+                .is_some_and(|gap| gap.contains(&'\n'))
+    };
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < offsets.len() {
+        let mut end = (start + max_tokens).min(offsets.len());
+        if end < offsets.len() {
+            let earliest = start + max_tokens / 2;
+            if let Some(boundary) = (earliest..end).rev().find(|&index| ends_sentence(index)) {
+                end = boundary + 1;
+            }
+        }
+        chunks.push(TextChunk {
+            start_char: offsets[start].0,
+            end_char: offsets[end - 1].1,
+        });
+        if end == offsets.len() {
+            break;
+        }
+        start = end.saturating_sub(overlap).max(start + 1);
+    }
+    Ok(chunks)
+}
+
+/// A chunk of a Markdown document with the headings it sits under, outermost first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MarkdownChunk {
+    pub heading_path: Vec<String>,
+    pub start_char: usize,
+    pub end_char: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum BlockKind {
+    Heading {
+        level: usize,
+        title: String,
+    },
+    /// Fenced code or a table, never split.
+    Atomic,
+    Paragraph,
+}
+
+/// Consecutive lines of one kind, `[start_char, end_char)` without the final newline.
+#[derive(Clone, Debug)]
+struct Block {
+    kind: BlockKind,
+    start_char: usize,
+    end_char: usize,
+}
+
+/// `# Title` to `###### Title`, returning the level and the title.
+fn parse_heading(line: &str) -> Option<(usize, String)> {
+    let line = line.trim_start();
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let rest = &line[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+        return None;
+    }
+    Some((
+        level,
+        rest.trim().trim_end_matches('#').trim_end().to_string(),
+    ))
+}
+
+fn fence_marker(line: &str) -> Option<&'static str> {
+    let line = line.trim_start();
+    ["```", "~~~"]
+        .into_iter()
+        .find(|marker| line.starts_with(marker))
+}
+
+fn is_table_row(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+/// Splits Markdown into headings, fenced code, tables and paragraphs.
+fn markdown_blocks(text: &str) -> Vec<Block> {
+    // (start_char, end_char, line) for every line
+    let mut lines = Vec::new();
+    let mut start_char = 0;
+    for line in text.split('\n') {
+        let len = line.chars().count();
+        lines.push((start_char, start_char + len, line));
+        start_char += len + 1;
+    }
+
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let (start_char, _, line) = lines[i];
+        if line.trim().is_empty() {
+            i += 1;
+            continue;
+        }
+        let (kind, last) = if let Some((level, title)) = parse_heading(line) {
+            (BlockKind::Heading { level, title }, i)
+        } else if let Some(marker) = fence_marker(line) {
+            // up to the closing fence, or the end of the text when it's missing
+            let close = (i + 1..lines.len())
+                .find(|&j| lines[j].2.trim_start().starts_with(marker))
+                .unwrap_or(lines.len() - 1);
+            (BlockKind::Atomic, close)
+        } else if is_table_row(line) {
+            let last = (i..lines.len())
+                .take_while(|&j| is_table_row(lines[j].2))
+                .last()
+                .unwrap_or(i);
+            (BlockKind::Atomic, last)
+        } else {
+            let last = (i..lines.len())
+                .take_while(|&j| {
+                    let line = lines[j].2;
+                    j == i
+                        || !(line.trim().is_empty()
+                            || parse_heading(line).is_some()
+                            || fence_marker(line).is_some()
+                            || is_table_row(line))
+                })
+                .last()
+                .unwrap_or(i);
+            (BlockKind::Paragraph, last)
+        };
+        blocks.push(Block {
+            kind,
+            start_char,
+            end_char: lines[last].1,
+        });
+        i = last + 1;
+    }
+    blocks
+}
+
+/// Splits a Markdown document into chunks of at most `max_tokens` tokens, given
+/// the `(start, end)` character spans of its tokens. Every heading starts a new
+/// chunk; the blocks of a section are packed together while they fit. Code
+/// blocks and tables are never split, even when alone they exceed the budget,
+/// while oversized paragraphs fall back to [`chunk_by_tokens`].
+pub fn split_markdown(
+    text: &str,
+    offsets: &[(usize, usize)],
+    max_tokens: usize,
+) -> Result<Vec<MarkdownChunk>, EmbeddingError> {
+    if max_tokens == 0 {
+        return Err(EmbeddingError::InvalidArgument(
+            "max_tokens must leave room for text".to_string(),
+        ));
+    }
+    let tokens_in = |start_char: usize, end_char: usize| {
+        let first = offsets.partition_point(|&(start, _)| start < start_char);
+        let last = offsets.partition_point(|&(start, _)| start < end_char);
+        first..last
+    };
+
+    let mut chunks = Vec::new();
+    let mut heading_path: Vec<(usize, String)> = Vec::new();
+    // the chunk being filled: (start_char, end_char, tokens)
+    let mut current: Option<(usize, usize, usize)> = None;
+    let path = |heading_path: &[(usize, String)]| {
+        heading_path
+            .iter()
+            .map(|(_, title)| title.clone())
+            .collect::<Vec<_>>()
+    };
+    let flush = |chunks: &mut Vec<MarkdownChunk>,
+                 current: &mut Option<(usize, usize, usize)>,
+                 heading_path: &[(usize, String)]| {
+        if let Some((start_char, end_char, _)) = current.take() {
+            chunks.push(MarkdownChunk {
+                heading_path: path(heading_path),
+                start_char,
+                end_char,
+            });
+        }
+    };
+
+    for block in markdown_blocks(text) {
+        let tokens = tokens_in(block.start_char, block.end_char);
+        let token_count = tokens.len();
+
+        if let BlockKind::Heading { level, title } = &block.kind {
+            flush(&mut chunks, &mut current, &heading_path);
+            heading_path.retain(|(outer, _)| outer < level);
+            heading_path.push((*level, title.clone()));
+            current = Some((block.start_char, block.end_char, token_count));
+            continue;
+        }
+
+        if let Some((_, end_char, used)) = &mut current {
+            if *used + token_count <= max_tokens {
+                *end_char = block.end_char;
+                *used += token_count;
+                continue;
+            }
+        }
+        flush(&mut chunks, &mut current, &heading_path);
+
+        if token_count <= max_tokens || block.kind == BlockKind::Atomic {
+            current = Some((block.start_char, block.end_char, token_count));
+            continue;
+        }
+        // an oversized paragraph: fixed-size pieces, the last one stays open
+        let mut pieces = chunk_by_tokens(text, &offsets[tokens.clone()], max_tokens, 0)?;
+        let last = pieces.pop();
+        for piece in pieces {
+            chunks.push(MarkdownChunk {
+                heading_path: path(&heading_path),
+                start_char: piece.start_char,
+                end_char: piece.end_char,
+            });
+        }
+        if let Some(last) = last {
+            let last_tokens = tokens_in(last.start_char, last.end_char).len();
+            current = Some((last.start_char, last.end_char, last_tokens));
+        }
+    }
+    flush(&mut chunks, &mut current, &heading_path);
+    Ok(chunks)
+}
+
+/// Lowercased abbreviations whose trailing period doesn't end a sentence.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "mt", "vs", "etc", "e.g", "i.e", "cf", "al",
+    "approx", "inc", "ltd", "co", "corp", "no", "fig", "vol", "pp", "ch", "sec", "art", "jan",
+    "feb", "mar", "apr", "jun", "jul", "aug", "sep", "sept", "oct", "nov", "dec",
+];
+
+/// Whether `segment` ends in an abbreviation or an initial such as "J.".
+fn ends_with_abbreviation(segment: &str) -> bool {
+    let Some(word) = segment.trim_end().strip_suffix('.') else {
+        return false;
+    };
+    let word = word
+        .rsplit(|c: char| c.is_whitespace() || c == '(' || c == '"')
+        .next()
+        .unwrap_or_default();
+    let mut chars = word.chars();
+    let is_initial = matches!((chars.next(), chars.next()), (Some(c), None) if c.is_uppercase());
+    is_initial || ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+/// Splits `text` into sentences: Unicode sentence boundaries (UAX #29), except
+/// after common abbreviations and initials. Spans exclude surrounding whitespace.
+pub fn split_sentences(text: &str) -> Vec<TextChunk> {
+    let mut sentences = Vec::new();
+    // byte range of the sentence being assembled
+    let mut current: Option<(usize, usize)> = None;
+    for (start, segment) in text.split_sentence_bound_indices() {
+        let (sentence_start, _) = *current.get_or_insert((start, start));
+        current = Some((sentence_start, start + segment.len()));
+        if !ends_with_abbreviation(segment) {
+            sentences.extend(current.take());
+        }
+    }
+    sentences.extend(current);
+
+    let mut char_count = 0;
+    let mut byte_to_char = vec![0; text.len() + 1];
+    for (byte, c) in text.char_indices() {
+        byte_to_char[byte] = char_count;
+        char_count += 1;
+        byte_to_char[byte + c.len_utf8()] = char_count;
+    }
+    sentences
+        .into_iter()
+        .filter_map(|(start, end)| {
+            let sentence = &text[start..end];
+            let leading = sentence.len() - sentence.trim_start().len();
+            let trimmed = sentence.trim();
+            (!trimmed.is_empty()).then(|| TextChunk {
+                start_char: byte_to_char[start + leading],
+                end_char: byte_to_char[start + leading + trimmed.len()],
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Character spans of the whitespace-separated words of `text`, standing in
+    /// for a tokenizer's offsets.
+    fn word_offsets(text: &str) -> Vec<(usize, usize)> {
+        let mut offsets = Vec::new();
+        let mut start = None;
+        for (index, c) in text.chars().chain(std::iter::once(' ')).enumerate() {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(index),
+                (true, Some(word_start)) => {
+                    offsets.push((word_start, index));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        offsets
+    }
+
+    fn spans(chunks: &[TextChunk]) -> Vec<(usize, usize)> {
+        chunks
+            .iter()
+            .map(|chunk| (chunk.start_char, chunk.end_char))
+            .collect()
+    }
+
+    #[test]
+    fn char_to_byte_offsets_handles_multibyte_characters() {
+        let text = "aé日b";
+        let byte_offsets = char_to_byte_offsets(text);
+        assert_eq!(byte_offsets, [0, 1, 3, 6, 7]);
+        assert_eq!(char_slice(text, &byte_offsets, 1, 3), "é日");
+        assert_eq!(char_to_byte_offsets(""), [0]);
+    }
+
+    #[test]
+    fn chunk_by_tokens_rejects_overlap_at_least_max_tokens() {
+        let offsets = word_offsets("a b c");
+        assert!(chunk_by_tokens("a b c", &offsets, 2, 2).is_err());
+        assert!(chunk_by_tokens("a b c", &offsets, 2, 3).is_err());
+        assert!(chunk_by_tokens("a b c", &offsets, 0, 0).is_err());
+    }
+
+    #[test]
+    fn chunk_by_tokens_of_empty_text_is_empty() {
+        assert_eq!(chunk_by_tokens("", &[], 4, 1).unwrap(), []);
+    }
+
+    #[test]
+    fn chunk_by_tokens_overlaps_consecutive_chunks() {
+        let text = "a b c d e f";
+        let chunks = chunk_by_tokens(text, &word_offsets(text), 4, 1).unwrap();
+        assert_eq!(spans(&chunks), [(0, 7), (6, 11)]);
+    }
+
+    #[test]
+    fn chunk_by_tokens_prefers_sentence_ends() {
+        let text = "One two three. Four five six.";
+        let chunks = chunk_by_tokens(text, &word_offsets(text), 4, 0).unwrap();
+        assert_eq!(spans(&chunks), [(0, 14), (15, 29)]);
+    }
+
+    #[test]
+    fn chunk_by_tokens_spans_count_characters_not_bytes() {
+        let text = "héllo wörld ünïcödé";
+        let chunks = chunk_by_tokens(text, &word_offsets(text), 2, 0).unwrap();
+        assert_eq!(spans(&chunks), [(0, 11), (12, 19)]);
+        let byte_offsets = char_to_byte_offsets(text);
+        assert_eq!(char_slice(text, &byte_offsets, 12, 19), "ünïcödé");
+    }
+
+    fn markdown_spans(chunks: &[MarkdownChunk]) -> Vec<(Vec<&str>, usize, usize)> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                (
+                    chunk.heading_path.iter().map(String::as_str).collect(),
+                    chunk.start_char,
+                    chunk.end_char,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_markdown_starts_a_chunk_at_every_heading() {
+        let text = "# A\nintro\n## B\nbody\n# C\nend";
+        let chunks = split_markdown(text, &word_offsets(text), 64).unwrap();
+        assert_eq!(
+            markdown_spans(&chunks),
+            [
+                (vec!["A"], 0, 9),
+                (vec!["A", "B"], 10, 19),
+                (vec!["C"], 20, 27),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_markdown_keeps_code_blocks_whole() {
+        let text = "```\na b c d e\n```";
+        let chunks = split_markdown(text, &word_offsets(text), 2).unwrap();
+        assert_eq!(markdown_spans(&chunks), [(vec![], 0, 17)]);
+    }
+
+    #[test]
+    fn split_markdown_splits_oversized_paragraphs() {
+        let text = "# T\na b c d e";
+        let chunks = split_markdown(text, &word_offsets(text), 2).unwrap();
+        assert_eq!(
+            markdown_spans(&chunks),
+            [
+                (vec!["T"], 0, 3),
+                (vec!["T"], 4, 7),
+                (vec!["T"], 8, 11),
+                (vec!["T"], 12, 13)
+            ]
+        );
+    }
+
+    #[test]
+    fn split_markdown_of_empty_text_is_empty() {
+        assert_eq!(split_markdown("", &[], 8).unwrap(), []);
+        assert!(split_markdown("# A", &word_offsets("# A"), 0).is_err());
+    }
+
+    fn sentence_spans(text: &str) -> Vec<(usize, usize)> {
+        spans(&split_sentences(text))
+    }
+
+    #[test]
+    fn split_sentences_skips_abbreviations_and_initials() {
+        assert_eq!(
+            sentence_spans("Dr. Smith met J. Doe at 5 p.m. on Friday. Was it late?  Yes!"),
+            [(0, 41), (42, 54), (56, 60)]
+        );
+        assert_eq!(sentence_spans("Mr. Brown arrived."), [(0, 18)]);
+        assert_eq!(
+            sentence_spans("Use a tool, e.g. a hammer. Done."),
+            [(0, 26), (27, 32)]
+        );
+    }
+
+    #[test]
+    fn split_sentences_reports_character_offsets() {
+        assert_eq!(sentence_spans("Über alles. Ça va?"), [(0, 11), (12, 18)]);
+    }
+
+    #[test]
+    fn split_sentences_of_blank_text_is_empty() {
+        assert!(split_sentences("").is_empty());
+        assert!(split_sentences("  \n\t ").is_empty());
+    }
+}
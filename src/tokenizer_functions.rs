@@ -0,0 +1,349 @@
+//! Scalar functions exposing a model's tokenizer, each taking an optional
+//! trailing model name like `embed(text, model)`.
+
+use crate::embed_utils::EmbeddingError;
+use crate::text_split::{char_slice, char_to_byte_offsets};
+use crate::{
+    map_rows_per_model, read_model_names, read_nullable_ints, read_nullable_strings,
+    write_list_output,
+};
+use duckdb::{
+    core::{DataChunkHandle, Inserter, LogicalTypeHandle, LogicalTypeId},
+    vscalar::{ScalarFunctionSignature, VScalar},
+    vtab::arrow::WritableVector,
+};
+use libduckdb_sys::duckdb_list_entry;
+use std::error::Error;
+
+/// `(arguments)` and `(arguments, model VARCHAR)` signatures returning `output`.
+fn with_optional_model(
+    arguments: impl Fn() -> Vec<LogicalTypeHandle>,
+    output: impl Fn() -> LogicalTypeHandle,
+) -> Vec<ScalarFunctionSignature> {
+    let mut with_model = arguments();
+    with_model.push(LogicalTypeId::Varchar.into());
+    vec![
+        ScalarFunctionSignature::exact(arguments(), output()),
+        ScalarFunctionSignature::exact(with_model, output()),
+    ]
+}
+
+/// Model per row for functions whose model is the argument at `model_column`,
+/// `None` where any argument before it is NULL.
+unsafe fn model_per_row(
+    input: &DataChunkHandle,
+    model_column: usize,
+    present: impl Fn(usize) -> bool,
+) -> Vec<Option<String>> {
+    read_model_names(input, model_column)
+        .into_iter()
+        .enumerate()
+        .map(|(row, model_name)| model_name.filter(|_| present(row)))
+        .collect()
+}
+
+/// Writes one VARCHAR per row, NULL where there is none.
+fn write_string_output(output: &mut dyn WritableVector, values: &[Option<String>]) {
+    let mut output_vector = output.flat_vector();
+    for (row, value) in values.iter().enumerate() {
+        match value {
+            Some(value) => output_vector.insert(row, value.as_str()),
+            None => output_vector.set_null(row),
+        }
+    }
+}
+
+/// Reads a `UINTEGER[]` column, mapping NULL rows to `None`.
+unsafe fn read_nullable_id_lists(input: &DataChunkHandle, column: usize) -> Vec<Option<Vec<u32>>> {
+    let list_vector = input.list_vector(column);
+    let child = list_vector.child(list_vector.len());
+    let ids = child.as_slice_with_len::<u32>(list_vector.len());
+    // the list's own data holds each row's offset and length into `ids`
+    let entries = input.flat_vector(column);
+    entries
+        .as_slice_with_len::<duckdb_list_entry>(input.len())
+        .iter()
+        .enumerate()
+        .map(|(row, entry)| {
+            (!entries.row_is_null(row as u64)).then(|| {
+                let offset = entry.offset as usize;
+                ids[offset..offset + entry.length as usize].to_vec()
+            })
+        })
+        .collect()
+}
+
+/// `is_truncated(text[, model])`: whether `embed(text[, model])` drops tokens.
+pub struct IsTruncatedFunc;
+
+impl VScalar for IsTruncatedFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let texts = read_nullable_strings(input, 0);
+        let models = model_per_row(input, 1, |row| texts[row].is_some());
+        let truncated = map_rows_per_model("tokenizing", models, |embedder, options, rows| {
+            let texts = rows.iter().filter_map(|&row| texts[row].clone()).collect();
+            Ok(embedder
+                .tokenize(texts, options)?
+                .into_iter()
+                .map(|tokenized| tokenized.truncated)
+                .collect())
+        })?;
+
+        let mut output_vector = output.flat_vector();
+        for (row, truncated) in truncated.into_iter().enumerate() {
+            match truncated {
+                Some(truncated) => output_vector.as_mut_slice::<bool>()[row] = truncated,
+                None => output_vector.set_null(row),
+            }
+        }
+        Ok(())
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        with_optional_model(
+            || vec![LogicalTypeId::Varchar.into()],
+            || LogicalTypeId::Boolean.into(),
+        )
+    }
+}
+
+/// `tokenize(text[, model])`: token ids as `UINTEGER[]`, special tokens included.
+pub struct TokenizeFunc;
+
+impl VScalar for TokenizeFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let texts = read_nullable_strings(input, 0);
+        let models = model_per_row(input, 1, |row| texts[row].is_some());
+        let ids = map_rows_per_model("tokenizing", models, |embedder, _, rows| {
+            let texts = rows.iter().filter_map(|&row| texts[row].clone()).collect();
+            Ok(embedder
+                .encode_full(texts)?
+                .into_iter()
+                .map(|(ids, _)| ids)
+                .collect())
+        })?;
+        write_list_output(output, &ids)
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        with_optional_model(
+            || vec![LogicalTypeId::Varchar.into()],
+            || LogicalTypeHandle::list(&LogicalTypeId::UInteger.into()),
+        )
+    }
+}
+
+/// `tokenize_pieces(text[, model])`: the token strings as `VARCHAR[]`, e.g.
+/// `[[CLS], hello, world, [SEP]]`.
+pub struct TokenizePiecesFunc;
+
+impl VScalar for TokenizePiecesFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let texts = read_nullable_strings(input, 0);
+        let models = model_per_row(input, 1, |row| texts[row].is_some());
+        let pieces = map_rows_per_model("tokenizing", models, |embedder, _, rows| {
+            let texts = rows.iter().filter_map(|&row| texts[row].clone()).collect();
+            Ok(embedder
+                .encode_full(texts)?
+                .into_iter()
+                .map(|(_, pieces)| pieces)
+                .collect())
+        })?;
+
+        let mut output_list_vector = output.list_vector();
+        let total_len: usize = pieces.iter().flatten().map(Vec::len).sum();
+        let child_vector = output_list_vector.child(total_len);
+        let mut offset = 0;
+        for (row, pieces) in pieces.iter().enumerate() {
+            let Some(pieces) = pieces else {
+                output_list_vector.set_entry(row, offset, 0);
+                output_list_vector.set_null(row);
+                continue;
+            };
+            for (i, piece) in pieces.iter().enumerate() {
+                child_vector.insert(offset + i, piece.as_str());
+            }
+            output_list_vector.set_entry(row, offset, pieces.len());
+            offset += pieces.len();
+        }
+        output_list_vector.set_len(total_len);
+        Ok(())
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        with_optional_model(
+            || vec![LogicalTypeId::Varchar.into()],
+            || LogicalTypeHandle::list(&LogicalTypeId::Varchar.into()),
+        )
+    }
+}
+
+/// `token_count(text[, model])`: number of tokens, special tokens included,
+/// before any truncation.
+pub struct TokenCountFunc;
+
+impl VScalar for TokenCountFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let texts = read_nullable_strings(input, 0);
+        let models = model_per_row(input, 1, |row| texts[row].is_some());
+        let counts = map_rows_per_model("tokenizing", models, |embedder, _, rows| {
+            let texts = rows.iter().filter_map(|&row| texts[row].clone()).collect();
+            Ok(embedder
+                .encode_full(texts)?
+                .into_iter()
+                .map(|(ids, _)| ids.len() as i64)
+                .collect())
+        })?;
+
+        let mut output_vector = output.flat_vector();
+        for (row, count) in counts.into_iter().enumerate() {
+            match count {
+                Some(count) => output_vector.as_mut_slice::<i64>()[row] = count,
+                None => output_vector.set_null(row),
+            }
+        }
+        Ok(())
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        with_optional_model(
+            || vec![LogicalTypeId::Varchar.into()],
+            || LogicalTypeId::Bigint.into(),
+        )
+    }
+}
+
+/// `detokenize(ids[, model])`: the text of a `UINTEGER[]` of token ids,
+/// special tokens left out.
+pub struct DetokenizeFunc;
+
+impl VScalar for DetokenizeFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let id_lists = read_nullable_id_lists(input, 0);
+        let models = model_per_row(input, 1, |row| id_lists[row].is_some());
+        let texts = map_rows_per_model("tokenizing", models, |embedder, _, rows| {
+            rows.iter()
+                .filter_map(|&row| id_lists[row].as_deref())
+                .map(|ids| embedder.decode(ids))
+                .collect()
+        })?;
+        write_string_output(output, &texts);
+        Ok(())
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        with_optional_model(
+            || vec![LogicalTypeHandle::list(&LogicalTypeId::UInteger.into())],
+            || LogicalTypeId::Varchar.into(),
+        )
+    }
+}
+
+/// `truncate_to_tokens(text, n[, model])`: the beginning of `text` covered by
+/// its first `n` tokens, special tokens not counted.
+pub struct TruncateToTokensFunc;
+
+impl VScalar for TruncateToTokensFunc {
+    type State = ();
+
+    /// # Safety
+    /// This function is called by DuckDB when executing the UDF (user-defined function).
+    /// - `input` must be a valid and initialized DataChunkHandle.
+    /// - `output` must be a valid and writable WritableVector.
+    /// - Caller (DuckDB) must guarantee input and output are valid for the duration of the call.
+    unsafe fn invoke(
+        _state: &(),
+        input: &mut DataChunkHandle,
+        output: &mut dyn WritableVector,
+    ) -> Result<(), Box<dyn Error>> {
+        let texts = read_nullable_strings(input, 0);
+        let limits = read_nullable_ints(input, 1);
+        if let Some(limit) = limits.iter().flatten().find(|&&limit| limit < 0) {
+            return Err(EmbeddingError::InvalidArgument(format!(
+                "n must not be negative, got {}",
+                limit
+            ))
+            .into());
+        }
+        let models = model_per_row(input, 2, |row| {
+            texts[row].is_some() && limits[row].is_some()
+        });
+        let truncated = map_rows_per_model("tokenizing", models, |embedder, _, rows| {
+            rows.iter()
+                .filter_map(|&row| Some((texts[row].as_deref()?, limits[row]? as usize)))
+                .map(|(text, limit)| {
+                    let offsets = embedder.token_offsets(text)?;
+                    if limit >= offsets.len() {
+                        return Ok(text.to_string());
+                    }
+                    let end_char = limit.checked_sub(1).map_or(0, |last| offsets[last].1);
+                    Ok(char_slice(text, &char_to_byte_offsets(text), 0, end_char).to_string())
+                })
+                .collect()
+        })?;
+        write_string_output(output, &truncated);
+        Ok(())
+    }
+
+    fn signatures() -> Vec<ScalarFunctionSignature> {
+        with_optional_model(
+            || vec![LogicalTypeId::Varchar.into(), LogicalTypeId::Integer.into()],
+            || LogicalTypeId::Varchar.into(),
+        )
+    }
+}